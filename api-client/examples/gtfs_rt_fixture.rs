@@ -0,0 +1,157 @@
+//! Writes `fixtures/gtfs-rt/feed.pb`, the feed the `gtfs_rt` tests decode.
+//!
+//! ```sh
+//! cargo run -p carris-api --example gtfs_rt_fixture --features gtfs-rt
+//! ```
+
+use carris_api::gtfs_rt::{
+    EntitySelector, FeedEntity, FeedHeader, FeedMessage, Position, RtAlert, RtTranslatedString,
+    RtTranslation, StopTimeEvent, StopTimeUpdate, TimeRange, TripDescriptor, TripUpdate,
+    VehicleDescriptor, VehiclePosition,
+};
+use prost::Message;
+use std::path::Path;
+
+/// 2026-01-05 08:00 in Lisbon.
+const NOW: u64 = 1_767_600_000;
+
+fn main() {
+    let feed = FeedMessage {
+        header: FeedHeader {
+            gtfs_realtime_version: "2.0".into(),
+            incrementality: Some(0),
+            timestamp: Some(NOW),
+        },
+        entity: vec![
+            // Two minutes late; the last stop only carries the delay.
+            FeedEntity {
+                id: "tu-1".into(),
+                trip_update: Some(TripUpdate {
+                    trip: trip("1001_0_1|1|1|0800", None),
+                    vehicle: Some(vehicle("41|1240")),
+                    stop_time_update: vec![
+                        stop_time(1, "020387", Some(120), Some(1_767_600_120)),
+                        stop_time(2, "140012", None, Some(1_767_601_320)),
+                        stop_time(3, "170500", Some(180), None),
+                    ],
+                    timestamp: Some(NOW),
+                    delay: Some(120),
+                }),
+                ..Default::default()
+            },
+            FeedEntity {
+                id: "tu-2".into(),
+                trip_update: Some(TripUpdate {
+                    trip: trip("1001_0_1|1|1|0830", Some(3)),
+                    stop_time_update: vec![stop_time(1, "020387", None, Some(1_767_601_800))],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            FeedEntity {
+                id: "v-1".into(),
+                vehicle: Some(VehiclePosition {
+                    trip: Some(trip("1001_0_1|1|1|0800", None)),
+                    position: Some(Position {
+                        latitude: 38.7363,
+                        longitude: -9.1389,
+                        bearing: Some(90.0),
+                        odometer: None,
+                        speed: Some(0.0),
+                    }),
+                    current_stop_sequence: Some(1),
+                    current_status: Some(1),
+                    timestamp: Some(NOW + 100),
+                    stop_id: Some("020387".into()),
+                    vehicle: Some(vehicle("41|1240")),
+                    occupancy_status: Some(1),
+                }),
+                ..Default::default()
+            },
+            // No current_status, which means in transit.
+            FeedEntity {
+                id: "v-2".into(),
+                vehicle: Some(VehiclePosition {
+                    trip: Some(trip("1001_0_1|1|1|0830", None)),
+                    position: Some(Position {
+                        latitude: 38.7223,
+                        longitude: -9.1393,
+                        bearing: None,
+                        odometer: None,
+                        speed: Some(8.5),
+                    }),
+                    timestamp: Some(NOW + 100),
+                    vehicle: Some(vehicle("41|1302")),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            FeedEntity {
+                id: "a-1".into(),
+                alert: Some(RtAlert {
+                    active_period: vec![TimeRange {
+                        start: Some(1_767_571_200),
+                        end: Some(1_767_657_600),
+                    }],
+                    informed_entity: vec![EntitySelector {
+                        route_id: Some("1001_0".into()),
+                        ..Default::default()
+                    }],
+                    cause: Some(4),
+                    effect: Some(2),
+                    url: None,
+                    header_text: Some(RtTranslatedString {
+                        translation: vec![translation("Greve", "pt"), translation("Strike", "en")],
+                    }),
+                    description_text: None,
+                }),
+                ..Default::default()
+            },
+        ],
+    };
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/gtfs-rt/feed.pb");
+    std::fs::write(&path, feed.encode_to_vec()).expect("cannot write the fixture");
+    println!("wrote {}", path.display());
+}
+
+fn trip(trip_id: &str, schedule_relationship: Option<i32>) -> TripDescriptor {
+    TripDescriptor {
+        trip_id: Some(trip_id.into()),
+        route_id: Some("1001_0".into()),
+        schedule_relationship,
+        ..Default::default()
+    }
+}
+
+fn vehicle(id: &str) -> VehicleDescriptor {
+    VehicleDescriptor {
+        id: Some(id.into()),
+        ..Default::default()
+    }
+}
+
+fn stop_time(
+    sequence: u32,
+    stop_id: &str,
+    delay: Option<i32>,
+    time: Option<i64>,
+) -> StopTimeUpdate {
+    StopTimeUpdate {
+        stop_sequence: Some(sequence),
+        stop_id: Some(stop_id.into()),
+        arrival: Some(StopTimeEvent {
+            delay,
+            time,
+            uncertainty: None,
+        }),
+        ..Default::default()
+    }
+}
+
+fn translation(text: &str, language: &str) -> RtTranslation {
+    RtTranslation {
+        text: text.into(),
+        language: Some(language.into()),
+    }
+}
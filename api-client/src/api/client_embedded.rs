@@ -1,24 +1,157 @@
-use core::fmt::Write;
-use embedded_io_async::Read;
-use reqwless::{client::HttpClient, request::Method};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_io_async::{ErrorKind, Read};
+use reqwless::client::{HttpClient, TlsConfig, TlsVerify};
+use reqwless::request::Method;
+use serde::de::DeserializeOwned;
 
-use crate::types::Arrival;
+use crate::api::{DEFAULT_BASE_URL, Endpoint};
+use crate::error::{Error, decode, decode_element, parse_retry_after};
+use crate::stream::{ArraySplitter, NextArrivals};
+use crate::types::{
+    Alert, AlertFeed, Arrival, CarrisAPI, District, FromTransport, Line, Municipality, Pattern,
+    Region, Shape, Stop, Vehicle,
+};
+
+/// The HTTP client and the buffers it reads responses into.
+pub struct HttpTransport<'a, TCP, DNS>
+where
+    TCP: embedded_nal_async::TcpConnect,
+    DNS: embedded_nal_async::Dns,
+{
+    pub http: HttpClient<'a, TCP, DNS>,
+    pub rx_buf: &'a mut [u8],
+    pub body_buf: &'a mut [u8],
+}
 
 pub struct CarrisClient<'a, TCP, DNS>
 where
     TCP: embedded_nal_async::TcpConnect,
     DNS: embedded_nal_async::Dns,
 {
-    http: HttpClient<'a, TCP, DNS>,
+    base_url: String,
+    // Requests borrow the transport mutably for their whole duration, so
+    // concurrent calls on a shared client wait for each other here.
+    transport: Mutex<NoopRawMutex, HttpTransport<'a, TCP, DNS>>,
+}
+
+/// How the connection to the API is secured.
+pub enum TlsMode<'a> {
+    /// Plain HTTP, e.g. for a stand-in of the API on the local network.
+    Plain,
+    /// TLS without verifying the server certificate, which embedded-tls
+    /// cannot do yet.
+    Unverified { seed: u64 },
+    /// TLS authenticated with a pre-shared key.
+    Psk {
+        seed: u64,
+        identity: &'a [u8],
+        psk: &'a [u8],
+    },
+}
+
+/// Sets up the HTTP client of a [`CarrisClient`] around buffers the caller
+/// owns, e.g. in a `static` for a client that lives as long as the
+/// firmware.
+pub struct CarrisClientBuilder<'a, TCP, DNS> {
+    tcp: &'a TCP,
+    dns: &'a DNS,
+    base_url: String,
+    tls: TlsMode<'a>,
     rx_buf: &'a mut [u8],
     body_buf: &'a mut [u8],
+    tls_bufs: Option<(&'a mut [u8], &'a mut [u8])>,
+}
+
+impl<'a, TCP, DNS> CarrisClientBuilder<'a, TCP, DNS>
+where
+    TCP: embedded_nal_async::TcpConnect,
+    DNS: embedded_nal_async::Dns,
+{
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Defaults to [`TlsMode::Plain`], which needs an `http://` base URL;
+    /// the default `https://` one needs a TLS mode with a random seed and
+    /// [`tls_buffers`](Self::tls_buffers).
+    pub fn tls(mut self, tls: TlsMode<'a>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// TLS record buffers. Servers may send records of up to 16640 bytes,
+    /// so `read` should be that large.
+    pub fn tls_buffers(mut self, read: &'a mut [u8], write: &'a mut [u8]) -> Self {
+        self.tls_bufs = Some((read, write));
+        self
+    }
+
+    /// Fails with [`Error::Config`] for an `https://` base URL without TLS,
+    /// or a TLS mode without [`tls_buffers`](Self::tls_buffers).
+    pub fn build(self) -> Result<CarrisClient<'a, TCP, DNS>, Error> {
+        check_tls(&self.base_url, &self.tls)?;
+
+        let tls_bufs = self.tls_bufs;
+        let tls = |seed, verify| match tls_bufs {
+            Some((read, write)) => Ok(TlsConfig::new(seed, read, write, verify)),
+            None => Err(Error::Config("TLS needs tls_buffers".into())),
+        };
+        let http = match self.tls {
+            TlsMode::Plain => HttpClient::new(self.tcp, self.dns),
+            TlsMode::Unverified { seed } => {
+                HttpClient::new_with_tls(self.tcp, self.dns, tls(seed, TlsVerify::None)?)
+            }
+            TlsMode::Psk {
+                seed,
+                identity,
+                psk,
+            } => HttpClient::new_with_tls(
+                self.tcp,
+                self.dns,
+                tls(seed, TlsVerify::Psk { identity, psk })?,
+            ),
+        };
+
+        Ok(CarrisClient::from_transport_with_base_url(
+            HttpTransport {
+                http,
+                rx_buf: self.rx_buf,
+                body_buf: self.body_buf,
+            },
+            &self.base_url,
+        ))
+    }
+}
+
+fn check_tls(base_url: &str, tls: &TlsMode<'_>) -> Result<(), Error> {
+    let https = base_url
+        .get(..8)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"));
+    if https && matches!(tls, TlsMode::Plain) {
+        return Err(Error::Config(format!("{base_url} needs a TLS mode")));
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-pub enum Error<E> {
-    Http(E),
-    Json(serde_json::Error),
-    TooLarge,
+/// Size of the chunks the response body is read in when streaming.
+const READ_CHUNK: usize = 256;
+
+/// Longest request URL: room for a long base URL and an id whose every
+/// byte is percent-encoded.
+const MAX_URL: usize = 384;
+
+/// Fails with [`Error::TooLarge`] rather than sending a truncated URL.
+fn url(base_url: &str, endpoint: Endpoint<'_>) -> Result<heapless::String<MAX_URL>, Error> {
+    let mut url = heapless::String::new();
+    endpoint
+        .write_url(base_url, &mut url)
+        .map_err(|_| Error::TooLarge)?;
+    Ok(url)
 }
 
 impl<'a, TCP, DNS> CarrisClient<'a, TCP, DNS>
@@ -31,53 +164,314 @@ where
         rx_buf: &'a mut [u8],
         body_buf: &'a mut [u8],
     ) -> Self {
-        Self {
+        Self::from_transport(HttpTransport {
             http,
             rx_buf,
             body_buf,
+        })
+    }
+
+    /// `rx_buf` holds the response status line and headers, `body_buf` a
+    /// response body or the largest element of a streamed array; 4 KB and
+    /// 8 KB fit the API's responses.
+    pub fn builder(
+        tcp: &'a TCP,
+        dns: &'a DNS,
+        rx_buf: &'a mut [u8],
+        body_buf: &'a mut [u8],
+    ) -> CarrisClientBuilder<'a, TCP, DNS> {
+        CarrisClientBuilder {
+            tcp,
+            dns,
+            base_url: DEFAULT_BASE_URL.into(),
+            tls: TlsMode::Plain,
+            rx_buf,
+            body_buf,
+            tls_bufs: None,
         }
     }
 
-    pub async fn arrivals_by_stop(
-        &mut self,
+    /// The `limit` soonest future arrivals at `stop_id`.
+    ///
+    /// Unlike [`CarrisAPI::arrivals_by_stop`] this never holds more than
+    /// `limit` arrivals, so it also works for busy stops on small heaps.
+    pub async fn next_arrivals_by_stop(
+        &self,
         stop_id: &str,
-    ) -> Result<alloc::vec::Vec<Arrival>, Error<reqwless::Error>> {
-        let mut url: heapless::String<128> = heapless::String::new();
-        write!(
-            &mut url,
-            "https://api.carrismetropolitana.pt/v2/arrivals/by_stop/{}",
-            stop_id
-        )
-        .map_err(|_| Error::TooLarge)?;
+        now_unix: i64,
+        limit: usize,
+    ) -> Result<Vec<Arrival>, Error> {
+        let mut next = NextArrivals::new(now_unix, limit);
+        self.get_json_seq(Endpoint::ArrivalsByStop(stop_id), |a| next.push(a))
+            .await?;
+        Ok(next.into_vec())
+    }
+
+    async fn get_json_vec<T: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint<'_>,
+    ) -> Result<Vec<T>, Error> {
+        let mut items = Vec::new();
+        self.get_json_seq(endpoint, |item| items.push(item)).await?;
+        Ok(items)
+    }
+
+    /// Streams a JSON array response, handing each element to `on_item`.
+    ///
+    /// `body_buf` only has to fit the largest single element.
+    async fn get_json_seq<T: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint<'_>,
+        mut on_item: impl FnMut(T),
+    ) -> Result<(), Error> {
+        let url = url(&self.base_url, endpoint)?;
+        let mut transport = self.transport.lock().await;
+        let HttpTransport {
+            http,
+            rx_buf,
+            body_buf,
+        } = &mut *transport;
+
+        let mut req = http
+            .request(Method::GET, url.as_str())
+            .await
+            .map_err(transport_error)?;
+
+        let response = req.send(rx_buf).await.map_err(transport_error)?;
+        if !response.status.is_successful() {
+            return Err(status_error(
+                response.status.0,
+                response.headers(),
+                endpoint,
+            ));
+        }
+        let mut reader = response.body().reader();
+
+        let mut splitter = ArraySplitter::new();
+        let mut chunk = [0u8; READ_CHUNK];
+        let mut index = 0usize;
+
+        loop {
+            let n = reader.read(&mut chunk).await.map_err(transport_error)?;
+            if n == 0 {
+                break;
+            }
+            splitter.feed(&chunk[..n], body_buf, |item| {
+                on_item(decode_element(item, index)?);
+                index += 1;
+                Ok(())
+            })?;
+        }
+
+        splitter.finish::<Error>()?;
+        Ok(())
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, endpoint: Endpoint<'_>) -> Result<T, Error> {
+        let url = url(&self.base_url, endpoint)?;
+        let mut transport = self.transport.lock().await;
+        let HttpTransport {
+            http,
+            rx_buf,
+            body_buf,
+        } = &mut *transport;
 
-        let mut req = self
-            .http
+        let mut req = http
             .request(Method::GET, url.as_str())
             .await
-            .map_err(Error::Http)?;
+            .map_err(transport_error)?;
 
-        let response = req.send(self.rx_buf).await.map_err(Error::Http)?;
+        let response = req.send(rx_buf).await.map_err(transport_error)?;
+        if !response.status.is_successful() {
+            return Err(status_error(
+                response.status.0,
+                response.headers(),
+                endpoint,
+            ));
+        }
 
         let mut total = 0usize;
         let mut reader = response.body().reader();
 
         loop {
-            if total >= self.body_buf.len() {
+            if total >= body_buf.len() {
                 return Err(Error::TooLarge);
             }
             let n = reader
-                .read(&mut self.body_buf[total..])
+                .read(&mut body_buf[total..])
                 .await
-                .map_err(Error::Http)?;
+                .map_err(transport_error)?;
             if n == 0 {
                 break;
             }
             total += n;
         }
 
-        let arrivals: alloc::vec::Vec<Arrival> =
-            serde_json::from_slice(&self.body_buf[..total]).map_err(Error::Json)?;
+        decode(&body_buf[..total])
+    }
+}
+
+fn transport_error(e: reqwless::Error) -> Error {
+    match e {
+        reqwless::Error::Network(ErrorKind::TimedOut) => Error::Timeout,
+        reqwless::Error::BufferTooSmall => Error::TooLarge,
+        reqwless::Error::InvalidUrl(e) => Error::Config(format!("invalid URL: {e:?}")),
+        e => Error::Transport(format!("{e:?}")),
+    }
+}
+
+fn status_error<'h>(
+    status: u16,
+    mut headers: impl Iterator<Item = (&'h str, &'h [u8])>,
+    endpoint: Endpoint<'_>,
+) -> Error {
+    let retry_after = headers
+        .find(|(name, _)| name.eq_ignore_ascii_case("retry-after"))
+        .and_then(|(_, value)| parse_retry_after(value));
+    Error::from_status(status, endpoint, retry_after)
+}
+
+impl<'a, TCP, DNS> FromTransport for CarrisClient<'a, TCP, DNS>
+where
+    TCP: embedded_nal_async::TcpConnect,
+    DNS: embedded_nal_async::Dns,
+{
+    type Transport = HttpTransport<'a, TCP, DNS>;
+
+    fn from_transport(transport: Self::Transport) -> Self {
+        Self::from_transport_with_base_url(transport, DEFAULT_BASE_URL)
+    }
+
+    fn from_transport_with_base_url(transport: Self::Transport, base_url: &str) -> Self {
+        Self {
+            base_url: base_url.into(),
+            transport: Mutex::new(transport),
+        }
+    }
+}
+
+impl<'a, TCP, DNS> CarrisAPI for CarrisClient<'a, TCP, DNS>
+where
+    TCP: embedded_nal_async::TcpConnect,
+    DNS: embedded_nal_async::Dns,
+{
+    async fn arrivals_by_stop(&self, stop_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json_vec(Endpoint::ArrivalsByStop(stop_id)).await
+    }
+
+    async fn arrivals_by_pattern(&self, pattern_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json_vec(Endpoint::ArrivalsByPattern(pattern_id))
+            .await
+    }
+
+    async fn arrivals_by_trip(&self, trip_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json_vec(Endpoint::ArrivalsByTrip(trip_id)).await
+    }
+
+    async fn get_all_stops(&self) -> Result<Vec<Stop>, Error> {
+        self.get_json_vec(Endpoint::Stops).await
+    }
+
+    async fn get_all_lines(&self) -> Result<Vec<Line>, Error> {
+        self.get_json_vec(Endpoint::Lines).await
+    }
+
+    async fn get_line(&self, line_id: &str) -> Result<Line, Error> {
+        self.get_json(Endpoint::Line(line_id)).await
+    }
+
+    async fn get_pattern(&self, pattern_id: &str) -> Result<Pattern, Error> {
+        self.get_json(Endpoint::Pattern(pattern_id)).await
+    }
+
+    async fn get_shape(&self, shape_id: &str) -> Result<Shape, Error> {
+        self.get_json(Endpoint::Shape(shape_id)).await
+    }
+
+    async fn get_all_vehicles(&self) -> Result<Vec<Vehicle>, Error> {
+        self.get_json_vec(Endpoint::Vehicles).await
+    }
+
+    async fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        self.get_json_vec(Endpoint::Municipalities).await
+    }
+
+    async fn get_districts(&self) -> Result<Vec<District>, Error> {
+        self.get_json_vec(Endpoint::Districts).await
+    }
+
+    async fn get_regions(&self) -> Result<Vec<Region>, Error> {
+        self.get_json_vec(Endpoint::Regions).await
+    }
+
+    async fn get_alerts(&self) -> Result<Vec<Alert>, Error> {
+        let feed: AlertFeed = self.get_json(Endpoint::Alerts).await?;
+        Ok(feed.into_alerts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_reqwless_errors() {
+        assert_eq!(
+            transport_error(reqwless::Error::Network(ErrorKind::TimedOut)),
+            Error::Timeout
+        );
+        assert_eq!(
+            transport_error(reqwless::Error::BufferTooSmall),
+            Error::TooLarge
+        );
+        assert!(transport_error(reqwless::Error::Dns).is_offline());
+        assert!(transport_error(reqwless::Error::ConnectionAborted).is_offline());
+    }
+
+    #[test]
+    fn rejects_urls_beyond_the_buffer() {
+        let id = "41|1234".repeat(8);
+        let encoded = url(DEFAULT_BASE_URL, Endpoint::ArrivalsByTrip(&id)).unwrap();
+        assert!(encoded.ends_with("41%7C123441%7C1234"));
+
+        let id = "|".repeat(MAX_URL / 3);
+        assert_eq!(
+            url(DEFAULT_BASE_URL, Endpoint::ArrivalsByTrip(&id)),
+            Err(Error::TooLarge)
+        );
+    }
+
+    #[test]
+    fn maps_statuses_and_retry_after() {
+        let headers = [
+            ("Content-Type", b"application/json".as_slice()),
+            ("retry-after", b" 30 ".as_slice()),
+        ];
+        assert_eq!(
+            status_error(429, headers.into_iter(), Endpoint::Stops),
+            Error::RateLimited {
+                retry_after_secs: Some(30)
+            }
+        );
+        assert_eq!(
+            status_error(503, headers.into_iter(), Endpoint::Vehicles),
+            Error::Status {
+                status: 503,
+                retry_after_secs: Some(30)
+            }
+        );
+        assert_eq!(
+            status_error(404, [].into_iter(), Endpoint::ArrivalsByStop("999999")),
+            Error::StopNotFound("999999".into())
+        );
+    }
 
-        Ok(arrivals)
+    #[test]
+    fn https_needs_tls() {
+        let err = check_tls(DEFAULT_BASE_URL, &TlsMode::Plain).unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+        assert!(check_tls("HTTPS://example.com", &TlsMode::Plain).is_err());
+        assert!(check_tls(DEFAULT_BASE_URL, &TlsMode::Unverified { seed: 1 }).is_ok());
+        assert!(check_tls("http://192.168.1.2:8080/v2", &TlsMode::Plain).is_ok());
     }
 }
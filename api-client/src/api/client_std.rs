@@ -1,40 +1,362 @@
-use crate::types::{Arrival, CarrisAPI, Stop};
-use alloc::string::String;
+use crate::api::{DEFAULT_BASE_URL, Endpoint};
+use crate::cache::{Conditional, Revalidate, Validators};
+use crate::error::{Error, decode, parse_retry_after};
+use crate::types::{
+    Alert, AlertFeed, Arrival, CarrisAPI, District, FromTransport, Line, Municipality, Pattern,
+    Region, Shape, Stop, Vehicle,
+};
+use alloc::string::{String, ToString};
+use core::time::Duration;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::de::DeserializeOwned;
 
 pub struct CarrisClient {
     base_url: String,
     client: reqwest::Client,
 }
 
-impl CarrisAPI for CarrisClient {
-    type Error = reqwest::Error;
+impl CarrisClient {
+    /// A client with the [`CarrisClientBuilder`] defaults.
+    pub fn new() -> Self {
+        Self::builder()
+            .build()
+            .expect("the default client configuration is valid")
+    }
+
+    pub fn new_with_base_url(base_url: &str) -> Self {
+        Self::builder()
+            .base_url(base_url)
+            .build()
+            .expect("the default client configuration is valid")
+    }
+
+    pub fn builder() -> CarrisClientBuilder {
+        CarrisClientBuilder::default()
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, endpoint: Endpoint<'_>) -> Result<T, Error> {
+        let response = self.client.get(endpoint.url(&self.base_url)).send().await?;
 
-    fn new() -> Self {
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| parse_retry_after(v.as_bytes()));
+            return Err(Error::from_status(status.as_u16(), endpoint, retry_after));
+        }
+
+        let body = response.bytes().await?;
+        decode(&body)
+    }
+}
+
+/// Configures the `reqwest::Client` of a [`CarrisClient`].
+///
+/// By default connecting times out after 10s and a stalled response after
+/// 30s, so a hung request cannot block its caller forever.
+#[derive(Debug, Clone)]
+pub struct CarrisClientBuilder {
+    base_url: String,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    proxy: Option<String>,
+    headers: Vec<(String, String)>,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+    #[cfg(feature = "brotli")]
+    brotli: bool,
+    root_certificates: Vec<Vec<u8>>,
+}
+
+impl Default for CarrisClientBuilder {
+    fn default() -> Self {
         Self {
-            base_url: "https://api.carrismetropolitana.pt/v2".to_string(),
-            client: reqwest::Client::new(),
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(30)),
+            timeout: None,
+            user_agent: concat!("carris-api/", env!("CARGO_PKG_VERSION")).to_owned(),
+            proxy: None,
+            headers: Vec::new(),
+            #[cfg(feature = "gzip")]
+            gzip: true,
+            #[cfg(feature = "brotli")]
+            brotli: true,
+            root_certificates: Vec::new(),
+        }
+    }
+}
+
+impl CarrisClientBuilder {
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.to_owned();
+        self
+    }
+
+    /// Time allowed to establish the connection, `None` to wait forever.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Time allowed between two reads of the response.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Time allowed for a whole request, from connecting to the last byte.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// Sends all requests through the HTTP proxy at `url`.
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.proxy = Some(url.to_owned());
+        self
+    }
+
+    /// Adds a header sent with every request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, enable: bool) -> Self {
+        self.gzip = enable;
+        self
+    }
+
+    #[cfg(feature = "brotli")]
+    pub fn brotli(mut self, enable: bool) -> Self {
+        self.brotli = enable;
+        self
+    }
+
+    /// Trusts the PEM encoded certificate in addition to the built-in roots,
+    /// e.g. for a TLS intercepting proxy.
+    pub fn root_certificate(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    pub fn build(self) -> Result<CarrisClient, Error> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::Config(format!("header {name}: {e}")))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| Error::Config(format!("header {name}: {e}")))?;
+            headers.append(name, value);
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .user_agent(&self.user_agent)
+            .default_headers(headers);
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(url) = &self.proxy {
+            let proxy =
+                reqwest::Proxy::all(url).map_err(|e| Error::Config(format!("proxy {url}: {e}")))?;
+            builder = builder.proxy(proxy);
+        }
+        #[cfg(feature = "gzip")]
+        {
+            builder = builder.gzip(self.gzip);
+        }
+        #[cfg(feature = "brotli")]
+        {
+            builder = builder.brotli(self.brotli);
+        }
+        let certificates = self
+            .root_certificates
+            .iter()
+            .map(|pem| reqwest::Certificate::from_pem(pem))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Config(format!("root certificate: {e}")))?;
+        builder = builder.tls_certs_merge(certificates);
+
+        let client = builder.build().map_err(|e| Error::Config(e.to_string()))?;
+        Ok(CarrisClient::from_transport_with_base_url(
+            client,
+            &self.base_url,
+        ))
+    }
+}
+
+impl Revalidate for CarrisClient {
+    async fn get_conditional(
+        &self,
+        endpoint: Endpoint<'_>,
+        validators: &Validators,
+    ) -> Result<Conditional, Error> {
+        use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+        let mut request = self.client.get(endpoint.url(&self.base_url));
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().await?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(Conditional::NotModified);
+        }
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| parse_retry_after(v.as_bytes()));
+            return Err(Error::from_status(status.as_u16(), endpoint, retry_after));
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let body = response.bytes().await?.to_vec();
+        Ok(Conditional::Modified { body, validators })
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Error::Timeout
+        } else {
+            Error::Transport(e.to_string())
         }
     }
+}
 
-    fn new_with_base_url(base_url: &str) -> Self {
+impl Default for CarrisClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromTransport for CarrisClient {
+    type Transport = reqwest::Client;
+
+    fn from_transport(client: reqwest::Client) -> Self {
+        Self::from_transport_with_base_url(client, DEFAULT_BASE_URL)
+    }
+
+    fn from_transport_with_base_url(client: reqwest::Client, base_url: &str) -> Self {
         Self {
             base_url: base_url.to_owned(),
-            client: reqwest::Client::new(),
+            client,
         }
     }
+}
+
+impl CarrisAPI for CarrisClient {
+    async fn arrivals_by_stop(&self, stop: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByStop(stop)).await
+    }
+
+    async fn arrivals_by_pattern(&self, pattern_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByPattern(pattern_id)).await
+    }
 
-    async fn arrivals_by_stop(&self, stop: &str) -> Result<Vec<Arrival>, reqwest::Error> {
-        let url = format!("{}/arrivals/by_stop/{}", self.base_url, stop);
-        self.client
-            .get(url)
-            .send()
-            .await?
-            .json::<Vec<Arrival>>()
-            .await
+    async fn arrivals_by_trip(&self, trip_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByTrip(trip_id)).await
     }
 
-    async fn get_all_stops(&self) -> Result<Vec<Stop>, reqwest::Error> {
-        let url = format!("{}/stops", self.base_url);
-        self.client.get(url).send().await?.json::<Vec<Stop>>().await
+    async fn get_all_stops(&self) -> Result<Vec<Stop>, Error> {
+        self.get_json(Endpoint::Stops).await
+    }
+
+    async fn get_all_lines(&self) -> Result<Vec<Line>, Error> {
+        self.get_json(Endpoint::Lines).await
+    }
+
+    async fn get_line(&self, line_id: &str) -> Result<Line, Error> {
+        self.get_json(Endpoint::Line(line_id)).await
+    }
+
+    async fn get_pattern(&self, pattern_id: &str) -> Result<Pattern, Error> {
+        self.get_json(Endpoint::Pattern(pattern_id)).await
+    }
+
+    async fn get_shape(&self, shape_id: &str) -> Result<Shape, Error> {
+        self.get_json(Endpoint::Shape(shape_id)).await
+    }
+
+    async fn get_all_vehicles(&self) -> Result<Vec<Vehicle>, Error> {
+        self.get_json(Endpoint::Vehicles).await
+    }
+
+    async fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        self.get_json(Endpoint::Municipalities).await
+    }
+
+    async fn get_districts(&self) -> Result<Vec<District>, Error> {
+        self.get_json(Endpoint::Districts).await
+    }
+
+    async fn get_regions(&self) -> Result<Vec<Region>, Error> {
+        self.get_json(Endpoint::Regions).await
+    }
+
+    async fn get_alerts(&self) -> Result<Vec<Alert>, Error> {
+        let feed: AlertFeed = self.get_json(Endpoint::Alerts).await?;
+        Ok(feed.into_alerts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_rejects_invalid_configuration() {
+        assert!(CarrisClient::builder().build().is_ok());
+        assert!(
+            CarrisClient::builder()
+                .header("X-Client", "desktop")
+                .proxy("http://localhost:3128")
+                .build()
+                .is_ok()
+        );
+
+        let invalid = [
+            CarrisClient::builder().header("bad header", "x"),
+            CarrisClient::builder().header("X-Client", "line\nbreak"),
+            CarrisClient::builder().proxy("not a url"),
+            CarrisClient::builder()
+                .root_certificate(b"-----BEGIN CERTIFICATE-----\n!!\n-----END CERTIFICATE-----\n"),
+        ];
+        for builder in invalid {
+            let err = builder.build().err().unwrap();
+            assert!(matches!(err, Error::Config(_)), "{err}");
+        }
     }
 }
@@ -0,0 +1,92 @@
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+/// A v2 API resource, rendered relative to a configurable base URL.
+///
+/// Ids are percent-encoded, so vehicle ids like `41|1234` are safe to pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint<'a> {
+    ArrivalsByStop(&'a str),
+    ArrivalsByPattern(&'a str),
+    ArrivalsByTrip(&'a str),
+    Stops,
+    Lines,
+    Line(&'a str),
+    Pattern(&'a str),
+    Shape(&'a str),
+    Vehicles,
+    Municipalities,
+    Districts,
+    Regions,
+    Alerts,
+}
+
+impl Endpoint<'_> {
+    /// Writes `base_url` followed by the endpoint path into `out`.
+    pub fn write_url<W: Write>(&self, base_url: &str, out: &mut W) -> fmt::Result {
+        write!(out, "{}{}", base_url.trim_end_matches('/'), self)
+    }
+
+    pub fn url(&self, base_url: &str) -> String {
+        let mut url = String::new();
+        self.write_url(base_url, &mut url)
+            .expect("writing to a String cannot fail");
+        url
+    }
+}
+
+impl fmt::Display for Endpoint<'_> {
+    /// The path of the endpoint, starting with `/`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (path, id) = match *self {
+            Endpoint::ArrivalsByStop(id) => ("/arrivals/by_stop/", Some(id)),
+            Endpoint::ArrivalsByPattern(id) => ("/arrivals/by_pattern/", Some(id)),
+            Endpoint::ArrivalsByTrip(id) => ("/arrivals/by_trip/", Some(id)),
+            Endpoint::Stops => ("/stops", None),
+            Endpoint::Lines => ("/lines", None),
+            Endpoint::Line(id) => ("/lines/", Some(id)),
+            Endpoint::Pattern(id) => ("/patterns/", Some(id)),
+            Endpoint::Shape(id) => ("/shapes/", Some(id)),
+            Endpoint::Vehicles => ("/vehicles", None),
+            Endpoint::Municipalities => ("/locations/municipalities", None),
+            Endpoint::Districts => ("/locations/districts", None),
+            Endpoint::Regions => ("/locations/regions", None),
+            Endpoint::Alerts => ("/alerts", None),
+        };
+
+        f.write_str(path)?;
+        if let Some(id) = id {
+            write_segment(f, id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+fn write_segment(f: &mut fmt::Formatter<'_>, segment: &str) -> fmt::Result {
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            f.write_char(b as char)?;
+        } else {
+            write!(f, "%{:02X}", b)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_base_url_and_encodes_ids() {
+        assert_eq!(
+            Endpoint::ArrivalsByStop("020387").url("http://localhost:8080/v2/"),
+            "http://localhost:8080/v2/arrivals/by_stop/020387"
+        );
+        assert_eq!(
+            Endpoint::Line("41|1234 a").url(crate::api::DEFAULT_BASE_URL),
+            "https://api.carrismetropolitana.pt/v2/lines/41%7C1234%20a"
+        );
+    }
+}
@@ -1,3 +1,10 @@
+/// Root of the Carris Metropolitana v2 API.
+pub const DEFAULT_BASE_URL: &str = "https://api.carrismetropolitana.pt/v2";
+
+mod endpoint;
+
+pub use endpoint::Endpoint;
+
 #[cfg(feature = "std")]
 mod client_std;
 
@@ -5,7 +12,7 @@ mod client_std;
 mod client_embedded;
 
 #[cfg(feature = "std")]
-pub use client_std::CarrisClient;
+pub use client_std::{CarrisClient, CarrisClientBuilder};
 
 #[cfg(feature = "embedded")]
-pub use client_embedded::CarrisClient;
+pub use client_embedded::{CarrisClient, CarrisClientBuilder, HttpTransport, TlsMode};
@@ -0,0 +1,528 @@
+//! Response cache for any [`CarrisAPI`] backend that can send conditional
+//! requests.
+//!
+//! Responses are kept as raw JSON together with their `ETag` and
+//! `Last-Modified` validators. Within an endpoint's TTL the stored body is
+//! served without touching the network; once it expires the request is
+//! revalidated and a `304 Not Modified` just refreshes the entry. While the
+//! API cannot be reached, entries are served up to a maximum staleness.
+
+use crate::Error;
+use crate::api::Endpoint;
+use crate::error::decode;
+use crate::types::{
+    Alert, AlertFeed, Arrival, CarrisAPI, District, FromTransport, Line, Municipality, Pattern,
+    Region, Shape, Stop, Vehicle,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Validators of a cached response, sent back as `If-None-Match` and
+/// `If-Modified-Since`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Outcome of a conditional GET.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conditional {
+    NotModified,
+    Modified {
+        body: Vec<u8>,
+        validators: Validators,
+    },
+}
+
+/// Backends able to send conditional GETs for raw response bodies.
+pub trait Revalidate {
+    fn get_conditional<'a>(
+        &'a self,
+        endpoint: Endpoint<'a>,
+        validators: &'a Validators,
+    ) -> impl Future<Output = Result<Conditional, Error>> + 'a;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    #[serde(skip)]
+    pub body: Vec<u8>,
+    pub validators: Validators,
+    /// When the body was last fetched or revalidated.
+    pub fetched_at_unix: u64,
+}
+
+/// Where cached responses are kept. Caching is best effort, so storage
+/// failures are not reported.
+pub trait CacheStore {
+    fn load(&self, key: &str) -> Option<CacheEntry>;
+    fn store(&self, key: &str, entry: &CacheEntry);
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn load(&self, key: &str) -> Option<CacheEntry> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    fn store(&self, key: &str, entry: &CacheEntry) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), entry.clone());
+    }
+}
+
+/// Keeps every response as `<name>.json` next to a `<name>.meta.json`
+/// holding its validators.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    /// `None` when there is nowhere to keep responses, so none are kept.
+    dir: Option<PathBuf>,
+}
+
+impl FsStore {
+    /// `dir` is created on the first store.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    fn paths(&self, key: &str) -> Option<(PathBuf, PathBuf)> {
+        let dir = self.dir.as_ref()?;
+        let name = key.trim_matches('/').replace('/', "_");
+        Some((
+            dir.join(format!("{name}.json")),
+            dir.join(format!("{name}.meta.json")),
+        ))
+    }
+}
+
+impl Default for FsStore {
+    /// A `carris-api` directory in the user's cache directory,
+    /// `$XDG_CACHE_HOME`, `~/.cache` or `%LOCALAPPDATA%`.
+    fn default() -> Self {
+        Self {
+            dir: user_cache_dir().map(|dir| dir.join("carris-api")),
+        }
+    }
+}
+
+fn user_cache_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| var("LOCALAPPDATA").map(PathBuf::from))
+}
+
+impl CacheStore for FsStore {
+    fn load(&self, key: &str) -> Option<CacheEntry> {
+        let (body_path, meta_path) = self.paths(key)?;
+        let mut entry: CacheEntry = serde_json::from_slice(&fs::read(meta_path).ok()?).ok()?;
+        entry.body = fs::read(body_path).ok()?;
+        Some(entry)
+    }
+
+    fn store(&self, key: &str, entry: &CacheEntry) {
+        let (Some(dir), Some((body_path, meta_path))) = (&self.dir, self.paths(key)) else {
+            return;
+        };
+        let Ok(meta) = serde_json::to_vec(entry) else {
+            return;
+        };
+        if fs::create_dir_all(dir).is_err() {
+            return;
+        }
+        // The metadata goes last, so a half written entry is never loaded.
+        let _ = fs::remove_file(&meta_path);
+        if fs::write(&body_path, &entry.body).is_ok() {
+            let _ = fs::write(&meta_path, meta);
+        }
+    }
+}
+
+/// How long responses of each kind are served without revalidation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTtl {
+    pub arrivals: Duration,
+    pub vehicles: Duration,
+    pub alerts: Duration,
+    /// Stops, lines, patterns, shapes and locations, which only change with
+    /// a new network schedule.
+    pub network: Duration,
+}
+
+impl Default for CacheTtl {
+    fn default() -> Self {
+        Self {
+            arrivals: Duration::from_secs(15),
+            vehicles: Duration::from_secs(10),
+            alerts: Duration::from_secs(5 * 60),
+            network: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl CacheTtl {
+    /// How long after fetching an entry may still be served while the API
+    /// cannot be reached: minutes for live data, a month for the network.
+    pub fn max_stale() -> Self {
+        Self {
+            arrivals: Duration::from_secs(2 * 60),
+            vehicles: Duration::from_secs(60),
+            alerts: Duration::from_secs(60 * 60),
+            network: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    pub fn for_endpoint(&self, endpoint: Endpoint<'_>) -> Duration {
+        match endpoint {
+            Endpoint::ArrivalsByStop(_)
+            | Endpoint::ArrivalsByPattern(_)
+            | Endpoint::ArrivalsByTrip(_) => self.arrivals,
+            Endpoint::Vehicles => self.vehicles,
+            Endpoint::Alerts => self.alerts,
+            Endpoint::Stops
+            | Endpoint::Lines
+            | Endpoint::Line(_)
+            | Endpoint::Pattern(_)
+            | Endpoint::Shape(_)
+            | Endpoint::Municipalities
+            | Endpoint::Districts
+            | Endpoint::Regions => self.network,
+        }
+    }
+}
+
+/// Wraps a [`CarrisAPI`] and caches its responses in a [`CacheStore`].
+///
+/// When the API cannot be reached an expired entry is served instead of
+/// the error, unless it is older than allowed by
+/// [`with_max_stale`](Self::with_max_stale).
+#[derive(Debug)]
+pub struct Cached<A, S> {
+    inner: A,
+    store: S,
+    ttl: CacheTtl,
+    max_stale: CacheTtl,
+}
+
+impl<A, S> Cached<A, S>
+where
+    A: Revalidate,
+    S: CacheStore,
+{
+    /// Serves entries offline for [`CacheTtl::max_stale`].
+    pub fn new(inner: A, store: S, ttl: CacheTtl) -> Self {
+        Self {
+            inner,
+            store,
+            ttl,
+            max_stale: CacheTtl::max_stale(),
+        }
+    }
+
+    /// Sets how old entries served while the API cannot be reached may be.
+    pub fn with_max_stale(mut self, max_stale: CacheTtl) -> Self {
+        self.max_stale = max_stale;
+        self
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, endpoint: Endpoint<'_>) -> Result<T, Error> {
+        let key = endpoint.to_string();
+        let now = now_unix_secs();
+        let cached = self.store.load(&key);
+
+        if let Some(entry) = &cached
+            && now < entry.fetched_at_unix + self.ttl.for_endpoint(endpoint).as_secs()
+        {
+            return decode(&entry.body);
+        }
+
+        let validators = cached
+            .as_ref()
+            .map(|entry| entry.validators.clone())
+            .unwrap_or_default();
+
+        match self.inner.get_conditional(endpoint, &validators).await {
+            Ok(Conditional::Modified { body, validators }) => {
+                let value = decode(&body)?;
+                self.store.store(
+                    &key,
+                    &CacheEntry {
+                        body,
+                        validators,
+                        fetched_at_unix: now,
+                    },
+                );
+                Ok(value)
+            }
+            Ok(Conditional::NotModified) => {
+                let mut entry = cached.ok_or(Error::from_status(304, endpoint, None))?;
+                entry.fetched_at_unix = now;
+                self.store.store(&key, &entry);
+                decode(&entry.body)
+            }
+            Err(e) if e.is_offline() => match cached {
+                Some(entry)
+                    if now
+                        <= entry.fetched_at_unix
+                            + self.max_stale.for_endpoint(endpoint).as_secs() =>
+                {
+                    decode(&entry.body)
+                }
+                _ => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn now_unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl<A, S> FromTransport for Cached<A, S>
+where
+    A: FromTransport + Revalidate,
+    S: CacheStore + Default,
+{
+    type Transport = A::Transport;
+
+    fn from_transport(transport: Self::Transport) -> Self {
+        Self::new(
+            A::from_transport(transport),
+            S::default(),
+            CacheTtl::default(),
+        )
+    }
+
+    fn from_transport_with_base_url(transport: Self::Transport, base_url: &str) -> Self {
+        Self::new(
+            A::from_transport_with_base_url(transport, base_url),
+            S::default(),
+            CacheTtl::default(),
+        )
+    }
+}
+
+impl<A, S> CarrisAPI for Cached<A, S>
+where
+    A: CarrisAPI + Revalidate,
+    S: CacheStore + Default,
+{
+    async fn arrivals_by_stop(&self, stop: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByStop(stop)).await
+    }
+
+    async fn arrivals_by_pattern(&self, pattern_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByPattern(pattern_id)).await
+    }
+
+    async fn arrivals_by_trip(&self, trip_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByTrip(trip_id)).await
+    }
+
+    async fn get_all_stops(&self) -> Result<Vec<Stop>, Error> {
+        self.get_json(Endpoint::Stops).await
+    }
+
+    async fn get_all_lines(&self) -> Result<Vec<Line>, Error> {
+        self.get_json(Endpoint::Lines).await
+    }
+
+    async fn get_line(&self, line_id: &str) -> Result<Line, Error> {
+        self.get_json(Endpoint::Line(line_id)).await
+    }
+
+    async fn get_pattern(&self, pattern_id: &str) -> Result<Pattern, Error> {
+        self.get_json(Endpoint::Pattern(pattern_id)).await
+    }
+
+    async fn get_shape(&self, shape_id: &str) -> Result<Shape, Error> {
+        self.get_json(Endpoint::Shape(shape_id)).await
+    }
+
+    async fn get_all_vehicles(&self) -> Result<Vec<Vehicle>, Error> {
+        self.get_json(Endpoint::Vehicles).await
+    }
+
+    async fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        self.get_json(Endpoint::Municipalities).await
+    }
+
+    async fn get_districts(&self) -> Result<Vec<District>, Error> {
+        self.get_json(Endpoint::Districts).await
+    }
+
+    async fn get_regions(&self) -> Result<Vec<Region>, Error> {
+        self.get_json(Endpoint::Regions).await
+    }
+
+    async fn get_alerts(&self) -> Result<Vec<Alert>, Error> {
+        let feed: AlertFeed = self.get_json(Endpoint::Alerts).await?;
+        Ok(feed.into_alerts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers 304 whenever the right ETag is sent, or fails while offline.
+    #[derive(Default)]
+    struct Origin {
+        requests: Mutex<Vec<Validators>>,
+        offline: bool,
+    }
+
+    impl Revalidate for Origin {
+        async fn get_conditional(
+            &self,
+            _endpoint: Endpoint<'_>,
+            validators: &Validators,
+        ) -> Result<Conditional, Error> {
+            self.requests.lock().unwrap().push(validators.clone());
+            if self.offline {
+                return Err(Error::Transport("connection refused".into()));
+            }
+            if validators.etag.as_deref() == Some("\"v1\"") {
+                return Ok(Conditional::NotModified);
+            }
+            Ok(Conditional::Modified {
+                body: br#"[{"id":"1","name":"Lisboa"}]"#.to_vec(),
+                validators: Validators {
+                    etag: Some("\"v1\"".into()),
+                    last_modified: None,
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_fresh_entries_and_revalidates_stale_ones() {
+        let ttl = CacheTtl {
+            network: Duration::ZERO,
+            ..Default::default()
+        };
+        let cached = Cached::new(Origin::default(), MemoryStore::new(), ttl);
+
+        let first: Vec<District> = cached.get_json(Endpoint::Districts).await.unwrap();
+        let second: Vec<District> = cached.get_json(Endpoint::Districts).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first[0].name, "Lisboa");
+
+        let requests = cached.inner().requests.lock().unwrap().clone();
+        assert_eq!(requests[0], Validators::default());
+        assert_eq!(requests[1].etag.as_deref(), Some("\"v1\""));
+
+        let fresh = Cached::new(Origin::default(), MemoryStore::new(), CacheTtl::default());
+        let _: Vec<District> = fresh.get_json(Endpoint::Districts).await.unwrap();
+        let _: Vec<District> = fresh.get_json(Endpoint::Districts).await.unwrap();
+        assert_eq!(fresh.inner().requests.lock().unwrap().len(), 1);
+    }
+
+    fn offline_with(entries: &[(Endpoint<'_>, u64)]) -> Cached<Origin, MemoryStore> {
+        let store = MemoryStore::new();
+        for (endpoint, age) in entries {
+            store.store(
+                &endpoint.to_string(),
+                &CacheEntry {
+                    body: b"[]".to_vec(),
+                    validators: Validators::default(),
+                    fetched_at_unix: now_unix_secs() - age,
+                },
+            );
+        }
+        let origin = Origin {
+            offline: true,
+            ..Default::default()
+        };
+        Cached::new(origin, store, CacheTtl::default())
+    }
+
+    #[tokio::test]
+    async fn serves_stale_entries_offline_up_to_their_limit() {
+        let cached = offline_with(&[
+            (Endpoint::ArrivalsByStop("020387"), 60),
+            (Endpoint::ArrivalsByStop("140012"), 60 * 60),
+            (Endpoint::Stops, 7 * 24 * 60 * 60),
+        ]);
+
+        let recent = cached
+            .get_json::<Vec<Arrival>>(Endpoint::ArrivalsByStop("020387"))
+            .await;
+        assert_eq!(recent, Ok(vec![]));
+        let stops = cached.get_json::<Vec<Stop>>(Endpoint::Stops).await;
+        assert_eq!(stops, Ok(vec![]));
+
+        // Hour old arrivals are worse than none.
+        let old = cached
+            .get_json::<Vec<Arrival>>(Endpoint::ArrivalsByStop("140012"))
+            .await;
+        assert_eq!(old, Err(Error::Transport("connection refused".into())));
+        assert_eq!(cached.inner().requests.lock().unwrap().len(), 3);
+
+        let strict =
+            offline_with(&[(Endpoint::Stops, 7 * 24 * 60 * 60)]).with_max_stale(CacheTtl {
+                network: Duration::from_secs(24 * 60 * 60),
+                ..CacheTtl::max_stale()
+            });
+        assert!(strict.get_json::<Vec<Stop>>(Endpoint::Stops).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn offline_without_an_entry_is_an_error() {
+        let cached = offline_with(&[]);
+        let result = cached.get_json::<Vec<Vehicle>>(Endpoint::Vehicles).await;
+        assert!(result.is_err_and(|e| e.is_offline()));
+    }
+
+    #[test]
+    fn fs_store_round_trips_entries() {
+        let dir = std::env::temp_dir().join(format!("carris-cache-{}", std::process::id()));
+        let store = FsStore::new(&dir);
+        let entry = CacheEntry {
+            body: br#"[{"id":"1","name":"Lisboa"}]"#.to_vec(),
+            validators: Validators {
+                etag: Some("\"v1\"".into()),
+                last_modified: None,
+            },
+            fetched_at_unix: 1_767_600_000,
+        };
+        assert_eq!(store.load("/locations/districts"), None);
+        store.store("/locations/districts", &entry);
+        assert_eq!(store.load("/locations/districts"), Some(entry.clone()));
+        fs::remove_dir_all(dir).unwrap();
+
+        let nowhere = FsStore { dir: None };
+        nowhere.store("/locations/districts", &entry);
+        assert_eq!(nowhere.load("/locations/districts"), None);
+    }
+}
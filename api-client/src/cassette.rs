@@ -0,0 +1,340 @@
+//! Record and replay of API sessions.
+//!
+//! In record mode [`Cassette`] passes every request on to the API and
+//! writes the raw responses to a directory, one `<endpoint>.json` file per
+//! endpoint. In replay mode it serves them from there without any network,
+//! decoding them with the current types, so checked-in cassettes catch
+//! schema changes in CI. `fixtures/cassette` is such a cassette, recorded
+//! from `carris-mock` by its `record_cassette` example.
+
+use crate::Error;
+use crate::api::Endpoint;
+use crate::cache::{Conditional, Revalidate, Validators};
+use crate::error::decode;
+use crate::types::{
+    Alert, AlertFeed, Arrival, CarrisAPI, District, Line, Municipality, Pattern, Region, Shape,
+    Stop, Vehicle,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One recorded response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub status: u16,
+    /// The response body, `null` for errors.
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Default)]
+struct Track {
+    interactions: Vec<Interaction>,
+    /// Next interaction to replay.
+    position: usize,
+}
+
+/// Wraps a [`CarrisAPI`] to record its responses, or replaces it with
+/// recorded ones.
+pub struct Cassette<A> {
+    inner: Option<A>,
+    dir: PathBuf,
+    tracks: Mutex<HashMap<String, Track>>,
+}
+
+impl<A: Revalidate> Cassette<A> {
+    /// Records the responses of `inner` into `dir`, replacing earlier
+    /// recordings of the same endpoints.
+    pub fn record(inner: A, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            inner: Some(inner),
+            dir: dir.into(),
+            tracks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Serves the responses recorded in `dir`.
+    ///
+    /// Repeated requests for an endpoint get its recorded responses in
+    /// order.
+    pub fn replay(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            inner: None,
+            dir: dir.into(),
+            tracks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self.inner {
+            Some(_) => Mode::Record,
+            None => Mode::Replay,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// In replay mode, when nothing was recorded for `endpoint` or its
+    /// recorded responses have all been served.
+    async fn get_json<T: DeserializeOwned>(&self, endpoint: Endpoint<'_>) -> Result<T, Error> {
+        let key = endpoint.to_string();
+        let interaction = match &self.inner {
+            Some(inner) => {
+                let interaction = record(inner, endpoint).await?;
+                self.append(&key, interaction.clone());
+                interaction
+            }
+            None => self.next(&key),
+        };
+
+        if interaction.status != 200 {
+            return Err(Error::from_status(interaction.status, endpoint, None));
+        }
+        let body = serde_json::to_vec(&interaction.body).expect("a JSON value serializes");
+        decode(&body)
+    }
+
+    fn append(&self, key: &str, interaction: Interaction) {
+        let mut tracks = self.tracks.lock().unwrap();
+        let track = tracks.entry(key.to_owned()).or_default();
+        track.interactions.push(interaction);
+
+        let path = cassette_path(&self.dir, key);
+        let written = fs::create_dir_all(&self.dir).and_then(|_| {
+            let json = serde_json::to_vec_pretty(&track.interactions)?;
+            fs::write(&path, json)
+        });
+        if let Err(e) = written {
+            panic!("cannot write cassette {}: {e}", path.display());
+        }
+    }
+
+    fn next(&self, key: &str) -> Interaction {
+        let mut tracks = self.tracks.lock().unwrap();
+        if !tracks.contains_key(key) {
+            let path = cassette_path(&self.dir, key);
+            let interactions: Vec<Interaction> = fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice(&json).ok())
+                .unwrap_or_else(|| panic!("no recording of {key} in {}", path.display()));
+            tracks.insert(
+                key.to_owned(),
+                Track {
+                    interactions,
+                    position: 0,
+                },
+            );
+        }
+
+        let track = tracks.get_mut(key).unwrap();
+        let interaction = track.interactions.get(track.position).cloned();
+        track.position += 1;
+        interaction.unwrap_or_else(|| {
+            panic!(
+                "request {} of {key}, but only {} were recorded",
+                track.position,
+                track.interactions.len()
+            )
+        })
+    }
+}
+
+/// Fetches `endpoint`, keeping HTTP errors as interactions too.
+async fn record<A: Revalidate>(inner: &A, endpoint: Endpoint<'_>) -> Result<Interaction, Error> {
+    match inner
+        .get_conditional(endpoint, &Validators::default())
+        .await
+    {
+        Ok(Conditional::Modified { body, .. }) => Ok(Interaction {
+            status: 200,
+            body: serde_json::from_slice(&body).map_err(|e| Error::Decode {
+                path: ".".into(),
+                message: e.to_string(),
+            })?,
+        }),
+        Ok(Conditional::NotModified) => Err(Error::from_status(304, endpoint, None)),
+        Err(Error::Status { status, .. }) => Ok(Interaction {
+            status,
+            body: Value::Null,
+        }),
+        Err(Error::StopNotFound(_) | Error::LineNotFound(_)) => Ok(Interaction {
+            status: 404,
+            body: Value::Null,
+        }),
+        Err(Error::RateLimited { .. }) => Ok(Interaction {
+            status: 429,
+            body: Value::Null,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+fn cassette_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.json", key.trim_matches('/').replace('/', "_")))
+}
+
+impl<A> CarrisAPI for Cassette<A>
+where
+    A: CarrisAPI + Revalidate,
+{
+    async fn arrivals_by_stop(&self, stop: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByStop(stop)).await
+    }
+
+    async fn arrivals_by_pattern(&self, pattern_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByPattern(pattern_id)).await
+    }
+
+    async fn arrivals_by_trip(&self, trip_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.get_json(Endpoint::ArrivalsByTrip(trip_id)).await
+    }
+
+    async fn get_all_stops(&self) -> Result<Vec<Stop>, Error> {
+        self.get_json(Endpoint::Stops).await
+    }
+
+    async fn get_all_lines(&self) -> Result<Vec<Line>, Error> {
+        self.get_json(Endpoint::Lines).await
+    }
+
+    async fn get_line(&self, line_id: &str) -> Result<Line, Error> {
+        self.get_json(Endpoint::Line(line_id)).await
+    }
+
+    async fn get_pattern(&self, pattern_id: &str) -> Result<Pattern, Error> {
+        self.get_json(Endpoint::Pattern(pattern_id)).await
+    }
+
+    async fn get_shape(&self, shape_id: &str) -> Result<Shape, Error> {
+        self.get_json(Endpoint::Shape(shape_id)).await
+    }
+
+    async fn get_all_vehicles(&self) -> Result<Vec<Vehicle>, Error> {
+        self.get_json(Endpoint::Vehicles).await
+    }
+
+    async fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        self.get_json(Endpoint::Municipalities).await
+    }
+
+    async fn get_districts(&self) -> Result<Vec<District>, Error> {
+        self.get_json(Endpoint::Districts).await
+    }
+
+    async fn get_regions(&self) -> Result<Vec<Region>, Error> {
+        self.get_json(Endpoint::Regions).await
+    }
+
+    async fn get_alerts(&self) -> Result<Vec<Alert>, Error> {
+        let feed: AlertFeed = self.get_json(Endpoint::Alerts).await?;
+        Ok(feed.into_alerts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Facility;
+
+    struct Origin;
+
+    impl Revalidate for Origin {
+        async fn get_conditional(
+            &self,
+            endpoint: Endpoint<'_>,
+            _validators: &Validators,
+        ) -> Result<Conditional, Error> {
+            match endpoint {
+                Endpoint::Districts => Ok(Conditional::Modified {
+                    body: br#"[{"id":"11","name":"Lisboa"}]"#.to_vec(),
+                    validators: Validators::default(),
+                }),
+                endpoint => Err(Error::from_status(404, endpoint, None)),
+            }
+        }
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("carris-cassette-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn replays_what_was_recorded() {
+        let dir = scratch_dir("roundtrip");
+        let recorder = Cassette::record(Origin, &dir);
+        let recorded: Vec<District> = recorder.get_json(Endpoint::Districts).await.unwrap();
+        let missing = recorder.get_json::<Line>(Endpoint::Line("9999")).await;
+
+        let player = Cassette::<Origin>::replay(&dir);
+        assert_eq!(player.mode(), Mode::Replay);
+        let replayed: Vec<District> = player.get_json(Endpoint::Districts).await.unwrap();
+        assert_eq!(recorded, replayed);
+        assert_eq!(
+            player.get_json::<Line>(Endpoint::Line("9999")).await,
+            missing
+        );
+        assert_eq!(missing, Err(Error::LineNotFound("9999".into())));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "request 2 of /locations/districts, but only 1 were recorded")]
+    async fn replay_fails_once_a_recording_runs_out() {
+        let dir = scratch_dir("exhausted");
+        let recorder = Cassette::record(Origin, &dir);
+        let _ = recorder
+            .get_json::<Vec<District>>(Endpoint::Districts)
+            .await;
+
+        let player = Cassette::<Origin>::replay(&dir);
+        let first = player.get_json::<Vec<District>>(Endpoint::Districts).await;
+        assert!(first.is_ok());
+        let _ = player.get_json::<Vec<District>>(Endpoint::Districts).await;
+    }
+
+    /// Decodes the cassette `record_cassette` in `carris-mock` recorded, so
+    /// schema changes show up here.
+    #[tokio::test]
+    async fn decodes_the_checked_in_cassette() {
+        let player = Cassette::<crate::api::CarrisClient>::replay(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/cassette"
+        ));
+
+        let stops = player.get_all_stops().await.unwrap();
+        assert_eq!(stops.len(), 2);
+        assert_eq!(stops[0].id, "020387");
+        assert_eq!(stops[0].facilities, [Facility::School]);
+        assert_eq!(stops[0].short_name, None);
+        assert!(!stops[1].wheelchair_boarding);
+
+        let arrivals = player.arrivals_by_stop("020387").await.unwrap();
+        assert_eq!(arrivals.len(), 3);
+        assert_eq!(arrivals[0].line_id, 1001);
+        assert_eq!(arrivals[0].trip_id.as_deref(), Some("1001_0_1|1|0800"));
+        assert_eq!(arrivals[0].vehicle_id.as_deref(), Some("41|1234"));
+        assert_eq!(arrivals[0].estimated_arrival_unix, Some(1_767_600_240));
+        assert_eq!(arrivals[2].observed_arrival_unix, Some(1_767_599_880));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "no recording of /stops")]
+    async fn replay_fails_on_unrecorded_requests() {
+        let player = Cassette::<Origin>::replay(scratch_dir("empty"));
+        let _ = player.get_json::<Vec<Stop>>(Endpoint::Stops).await;
+    }
+}
@@ -0,0 +1,150 @@
+use crate::api::Endpoint;
+use crate::stream::StreamError;
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+use serde::de::DeserializeOwned;
+
+/// Errors returned by every [`CarrisAPI`](crate::types::CarrisAPI) backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The request never got a response: DNS, connect, TLS or a dropped
+    /// connection.
+    Transport(String),
+    Timeout,
+    /// The server answered with a non-success status not covered below,
+    /// such as a 503 that may say when to come back.
+    Status {
+        status: u16,
+        retry_after_secs: Option<u64>,
+    },
+    /// The response body did not match the expected schema.
+    Decode {
+        /// Where decoding failed, e.g. `[3].line_id`.
+        path: String,
+        message: String,
+    },
+    StopNotFound(String),
+    LineNotFound(String),
+    RateLimited {
+        retry_after_secs: Option<u64>,
+    },
+    /// A URL or response element did not fit the client's buffers.
+    TooLarge,
+    /// The client was built with an invalid proxy, header or certificate.
+    Config(String),
+    /// Reading a local file, such as a GTFS feed, failed.
+    Io(String),
+}
+
+impl Error {
+    /// Maps a non-success HTTP status of a request to `endpoint`.
+    pub fn from_status(status: u16, endpoint: Endpoint<'_>, retry_after_secs: Option<u64>) -> Self {
+        match (status, endpoint) {
+            (404, Endpoint::ArrivalsByStop(id)) => Error::StopNotFound(id.to_owned()),
+            (404, Endpoint::Line(id)) => Error::LineNotFound(id.to_owned()),
+            (429, _) => Error::RateLimited { retry_after_secs },
+            (status, _) => Error::Status {
+                status,
+                retry_after_secs,
+            },
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            Error::StopNotFound(_) | Error::LineNotFound(_) | Error::Status { status: 404, .. }
+        )
+    }
+
+    /// The API could not be reached at all.
+    pub fn is_offline(&self) -> bool {
+        matches!(self, Error::Transport(_) | Error::Timeout)
+    }
+}
+
+impl From<StreamError<Error>> for Error {
+    fn from(e: StreamError<Error>) -> Self {
+        match e {
+            StreamError::TooLarge => Error::TooLarge,
+            StreamError::Malformed => Error::Decode {
+                path: ".".into(),
+                message: "expected a JSON array of objects".into(),
+            },
+            StreamError::Item(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {e}"),
+            Error::Timeout => f.write_str("request timed out"),
+            Error::Status { status, .. } => write!(f, "unexpected HTTP status {status}"),
+            Error::Decode { path, message } => write!(f, "cannot decode {path}: {message}"),
+            Error::StopNotFound(id) => write!(f, "stop {id} does not exist"),
+            Error::LineNotFound(id) => write!(f, "line {id} does not exist"),
+            Error::RateLimited {
+                retry_after_secs: Some(secs),
+            } => write!(f, "rate limited, retry after {secs}s"),
+            Error::RateLimited {
+                retry_after_secs: None,
+            } => f.write_str("rate limited"),
+            Error::TooLarge => f.write_str("URL or response does not fit into the buffer"),
+            Error::Config(e) => write!(f, "invalid client configuration: {e}"),
+            Error::Io(e) => write!(f, "cannot read {e}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Deserializes a JSON document, recording where decoding failed.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let de = &mut serde_json::Deserializer::from_slice(bytes);
+    serde_path_to_error::deserialize(de).map_err(|e| Error::Decode {
+        path: e.path().to_string(),
+        message: e.into_inner().to_string(),
+    })
+}
+
+/// Like [`decode`] for the `index`-th element of a streamed array, so paths
+/// read the same as when the whole array is decoded at once.
+pub fn decode_element<T: DeserializeOwned>(bytes: &[u8], index: usize) -> Result<T, Error> {
+    decode(bytes).map_err(|e| match e {
+        Error::Decode { path, message } if path == "." => Error::Decode {
+            path: format!("[{index}]"),
+            message,
+        },
+        Error::Decode { path, message } => Error::Decode {
+            path: format!("[{index}].{path}"),
+            message,
+        },
+        e => e,
+    })
+}
+
+/// Parses a `Retry-After` header given in seconds.
+pub fn parse_retry_after(value: &[u8]) -> Option<u64> {
+    core::str::from_utf8(value).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Arrival;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn decode_errors_carry_the_offending_path() {
+        let body = br#"[{"line_id":"1001","headsign":"A"},{"line_id":"x","headsign":"B"}]"#;
+        let err = decode::<Vec<Arrival>>(body).unwrap_err();
+        assert!(matches!(err, Error::Decode { ref path, .. } if path == "[1].line_id"));
+
+        let err = decode_element::<Arrival>(br#"{"line_id":"x","headsign":"B"}"#, 1).unwrap_err();
+        assert!(matches!(err, Error::Decode { ref path, .. } if path == "[1].line_id"));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::types::{Shape, Stop};
+use alloc::vec::Vec;
+use serde_json::{Value, json};
+
+/// Builds a GeoJSON `FeatureCollection` with the shape as a `LineString`
+/// followed by one `Point` feature per stop.
+///
+/// Coordinates are emitted as `[lon, lat]` as required by RFC 7946.
+pub fn shape_to_feature_collection(shape: &Shape, stops: &[Stop]) -> Value {
+    let coordinates: Vec<Value> = shape
+        .points
+        .iter()
+        .map(|p| json!([p.shape_pt_lon, p.shape_pt_lat]))
+        .collect();
+
+    let mut features = Vec::with_capacity(stops.len() + 1);
+    features.push(json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "LineString",
+            "coordinates": coordinates,
+        },
+        "properties": {
+            "shape_id": shape.id,
+            "extension": shape.extension,
+        },
+    }));
+
+    features.extend(stops.iter().map(stop_to_feature));
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+pub fn stop_to_feature(stop: &Stop) -> Value {
+    json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "Point",
+            "coordinates": [stop.lon, stop.lat],
+        },
+        "properties": {
+            "stop_id": stop.id,
+            "name": stop.long_name,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ShapePoint;
+    use alloc::vec;
+
+    #[test]
+    fn emits_lon_lat_features() {
+        let shape = Shape {
+            id: "p1_1001_0_1".into(),
+            points: vec![
+                ShapePoint {
+                    shape_pt_lat: 38.7363,
+                    shape_pt_lon: -9.1451,
+                    shape_pt_sequence: 1,
+                    shape_dist_traveled: 0.0,
+                },
+                ShapePoint {
+                    shape_pt_lat: 38.7306,
+                    shape_pt_lon: -9.1469,
+                    shape_pt_sequence: 2,
+                    shape_dist_traveled: 650.0,
+                },
+            ],
+            extension: 650.0,
+        };
+        let saldanha = Stop {
+            id: "020387".into(),
+            lat: 38.7363,
+            lon: -9.1451,
+            long_name: "Saldanha".into(),
+            ..Default::default()
+        };
+
+        let collection = shape_to_feature_collection(&shape, &[saldanha]);
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(
+            features[0]["geometry"]["coordinates"],
+            json!([[-9.1451, 38.7363], [-9.1469, 38.7306]])
+        );
+        assert_eq!(
+            features[0]["properties"],
+            json!({"shape_id": "p1_1001_0_1", "extension": 650.0})
+        );
+
+        assert_eq!(
+            features[1],
+            json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [-9.1451, 38.7363]},
+                "properties": {"stop_id": "020387", "name": "Saldanha"},
+            })
+        );
+    }
+}
@@ -0,0 +1,786 @@
+//! Import of the static GTFS feed Carris publishes as a zip.
+//!
+//! [`GtfsFeed`] holds the rows of the feed as typed records and maps them
+//! onto the [`Stop`], [`Line`], [`Pattern`] and [`Shape`] types the API
+//! returns, so the apps can run from a downloaded feed alone. Stop times,
+//! by far the largest table, keep only the columns used here and share
+//! their ids and times between rows.
+//!
+//! Carris adds a few columns to the standard files, such as `line_id` in
+//! `routes.txt` and `pattern_id` in `trips.txt`. They are optional, and
+//! services may be given by `calendar.txt`, `calendar_dates.txt` or both,
+//! so feeds of other agencies load as well.
+
+use crate::Error;
+use crate::timetable::{Timetable, Trip};
+use crate::types::{
+    Line, PathStop, Pattern, PatternTrip, ScheduledStop, ServiceDate, Shape, ShapePoint, Stop,
+};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GtfsStop {
+    pub stop_id: String,
+    #[serde(default)]
+    pub stop_code: Option<String>,
+    pub stop_name: String,
+    #[serde(default)]
+    pub tts_stop_name: Option<String>,
+    pub stop_lat: f64,
+    pub stop_lon: f64,
+    #[serde(default)]
+    pub municipality_id: Option<String>,
+    #[serde(default)]
+    pub district_id: Option<String>,
+    #[serde(default)]
+    pub region_id: Option<String>,
+    /// `1` when the stop is accessible by wheelchair.
+    #[serde(default)]
+    pub wheelchair_boarding: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GtfsRoute {
+    pub route_id: String,
+    #[serde(default)]
+    pub line_id: Option<String>,
+    #[serde(default)]
+    pub route_short_name: String,
+    #[serde(default)]
+    pub route_long_name: String,
+    #[serde(default)]
+    pub route_type: Option<u16>,
+    /// Hex colour without the leading `#`.
+    #[serde(default)]
+    pub route_color: Option<String>,
+    #[serde(default)]
+    pub route_text_color: Option<String>,
+}
+
+impl GtfsRoute {
+    /// The line this route belongs to; standard feeds have one route per
+    /// line.
+    pub fn line_id(&self) -> &str {
+        self.line_id.as_deref().unwrap_or(&self.route_id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GtfsTrip {
+    pub route_id: String,
+    pub service_id: String,
+    pub trip_id: String,
+    #[serde(default)]
+    pub pattern_id: Option<String>,
+    #[serde(default)]
+    pub trip_headsign: Option<String>,
+    #[serde(default)]
+    pub direction_id: Option<u8>,
+    #[serde(default)]
+    pub shape_id: Option<String>,
+}
+
+impl GtfsTrip {
+    /// The pattern this trip follows. Without a `pattern_id` column, trips
+    /// of a route sharing direction and shape form a pattern.
+    pub fn pattern_id(&self) -> String {
+        match &self.pattern_id {
+            Some(id) => id.clone(),
+            None => format!(
+                "{}_{}_{}",
+                self.route_id,
+                self.direction_id.unwrap_or_default(),
+                self.shape_id.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// A row of `stop_times.txt`; rows with equal ids or times share them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GtfsStopTime {
+    pub trip_id: Arc<str>,
+    /// `HH:MM:SS`, may exceed `24:00:00` for trips past midnight.
+    pub arrival_time: Arc<str>,
+    pub stop_id: Arc<str>,
+    pub stop_sequence: u16,
+    /// `1` when passengers cannot board here.
+    pub pickup_type: Option<u8>,
+    /// `1` when passengers cannot alight here.
+    pub drop_off_type: Option<u8>,
+    pub shape_dist_traveled: Option<f64>,
+}
+
+/// A row of `stop_times.txt` borrowed from the CSV record.
+#[derive(Deserialize)]
+struct StopTimeRow<'a> {
+    trip_id: &'a str,
+    arrival_time: &'a str,
+    stop_id: &'a str,
+    stop_sequence: u16,
+    #[serde(default)]
+    pickup_type: Option<u8>,
+    #[serde(default)]
+    drop_off_type: Option<u8>,
+    #[serde(default)]
+    shape_dist_traveled: Option<f64>,
+}
+
+/// A weekly service; each weekday is `1` when the service runs on it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GtfsCalendar {
+    pub service_id: String,
+    pub monday: u8,
+    pub tuesday: u8,
+    pub wednesday: u8,
+    pub thursday: u8,
+    pub friday: u8,
+    pub saturday: u8,
+    pub sunday: u8,
+    /// `YYYYMMDD`, inclusive.
+    pub start_date: String,
+    /// `YYYYMMDD`, inclusive.
+    pub end_date: String,
+}
+
+impl GtfsCalendar {
+    fn runs_on(&self, date: ServiceDate) -> bool {
+        // 1970-01-01 was a Thursday.
+        let weekday = match (date.epoch_days() + 3).rem_euclid(7) {
+            0 => self.monday,
+            1 => self.tuesday,
+            2 => self.wednesday,
+            3 => self.thursday,
+            4 => self.friday,
+            5 => self.saturday,
+            _ => self.sunday,
+        };
+        weekday == 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GtfsCalendarDate {
+    pub service_id: String,
+    /// `YYYYMMDD`.
+    pub date: String,
+    /// `1` adds the date to the service, `2` removes it.
+    pub exception_type: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GtfsShapePoint {
+    pub shape_id: String,
+    pub shape_pt_lat: f64,
+    pub shape_pt_lon: f64,
+    pub shape_pt_sequence: u32,
+    #[serde(default)]
+    pub shape_dist_traveled: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GtfsFeed {
+    pub stops: Vec<GtfsStop>,
+    pub routes: Vec<GtfsRoute>,
+    pub trips: Vec<GtfsTrip>,
+    pub stop_times: Vec<GtfsStopTime>,
+    pub calendar: Vec<GtfsCalendar>,
+    pub calendar_dates: Vec<GtfsCalendarDate>,
+    pub shapes: Vec<GtfsShapePoint>,
+}
+
+impl GtfsFeed {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::Io(format!("{}: {e}", path.display())))?;
+        Self::from_zip(BufReader::new(file))
+    }
+
+    pub fn from_zip<R: Read + Seek>(reader: R) -> Result<Self, Error> {
+        let mut zip = zip::ZipArchive::new(reader).map_err(|e| Error::Io(e.to_string()))?;
+        Ok(Self {
+            stops: read_table(&mut zip, "stops.txt", true)?,
+            routes: read_table(&mut zip, "routes.txt", true)?,
+            trips: read_table(&mut zip, "trips.txt", true)?,
+            stop_times: read_stop_times(&mut zip)?,
+            calendar: read_table(&mut zip, "calendar.txt", false)?,
+            calendar_dates: read_table(&mut zip, "calendar_dates.txt", false)?,
+            shapes: read_table(&mut zip, "shapes.txt", false)?,
+        })
+    }
+
+    /// Service dates of `service_id` as `YYYYMMDD`, in order.
+    pub fn service_dates(&self, service_id: &str) -> Vec<String> {
+        self.services()
+            .remove(service_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(gtfs_date)
+            .collect()
+    }
+
+    /// The dates of every service: the weekdays of `calendar.txt` in their
+    /// range, with the additions and removals of `calendar_dates.txt`.
+    fn services(&self) -> HashMap<&str, BTreeSet<ServiceDate>> {
+        let mut services: HashMap<&str, BTreeSet<ServiceDate>> = HashMap::new();
+        for calendar in &self.calendar {
+            let dates = services.entry(calendar.service_id.as_str()).or_default();
+            let (Some(start), Some(end)) = (
+                ServiceDate::parse(&calendar.start_date),
+                ServiceDate::parse(&calendar.end_date),
+            ) else {
+                continue;
+            };
+            dates.extend(
+                (start.epoch_days()..=end.epoch_days())
+                    .map(ServiceDate::from_epoch_days)
+                    .filter(|d| calendar.runs_on(*d)),
+            );
+        }
+        for exception in &self.calendar_dates {
+            let Some(date) = ServiceDate::parse(&exception.date) else {
+                continue;
+            };
+            let dates = services.entry(exception.service_id.as_str()).or_default();
+            match exception.exception_type {
+                1 => dates.insert(date),
+                _ => dates.remove(&date),
+            };
+        }
+        services
+    }
+
+    /// Stop times of every trip, ordered by `stop_sequence`.
+    fn stop_times_by_trip(&self) -> HashMap<&str, Vec<&GtfsStopTime>> {
+        let mut stop_times: HashMap<&str, Vec<&GtfsStopTime>> = HashMap::new();
+        for stop_time in &self.stop_times {
+            stop_times
+                .entry(&*stop_time.trip_id)
+                .or_default()
+                .push(stop_time);
+        }
+        for times in stop_times.values_mut() {
+            times.sort_by_key(|st| st.stop_sequence);
+        }
+        stop_times
+    }
+
+    pub fn to_stops(&self) -> Vec<Stop> {
+        let routes: HashMap<&str, &GtfsRoute> = self
+            .routes
+            .iter()
+            .map(|r| (r.route_id.as_str(), r))
+            .collect();
+        let trips: HashMap<&str, &GtfsTrip> =
+            self.trips.iter().map(|t| (t.trip_id.as_str(), t)).collect();
+
+        #[derive(Default)]
+        struct Served {
+            lines: BTreeSet<String>,
+            routes: BTreeSet<String>,
+            patterns: BTreeSet<String>,
+        }
+        let mut served: HashMap<&str, Served> = HashMap::new();
+        for stop_time in &self.stop_times {
+            let Some(trip) = trips.get(&*stop_time.trip_id) else {
+                continue;
+            };
+            let entry = served.entry(&*stop_time.stop_id).or_default();
+            if let Some(route) = routes.get(trip.route_id.as_str()) {
+                entry.lines.insert(route.line_id().to_owned());
+            }
+            entry.routes.insert(trip.route_id.clone());
+            entry.patterns.insert(trip.pattern_id());
+        }
+
+        self.stops
+            .iter()
+            .map(|s| {
+                let served = served.remove(s.stop_id.as_str()).unwrap_or_default();
+                Stop {
+                    id: s.stop_id.clone(),
+                    short_name: s.stop_code.clone(),
+                    long_name: s.stop_name.clone(),
+                    tts_name: s
+                        .tts_stop_name
+                        .clone()
+                        .unwrap_or_else(|| s.stop_name.clone()),
+                    lat: s.stop_lat,
+                    lon: s.stop_lon,
+                    municipality_id: s.municipality_id.clone(),
+                    district_id: s.district_id.clone(),
+                    region_id: s.region_id.clone(),
+                    wheelchair_boarding: s.wheelchair_boarding == Some(1),
+                    line_ids: served.lines.into_iter().collect(),
+                    route_ids: served.routes.into_iter().collect(),
+                    pattern_ids: served.patterns.into_iter().collect(),
+                    facilities: Vec::new(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_lines(&self) -> Vec<Line> {
+        let mut lines: Vec<Line> = Vec::new();
+        let mut index: HashMap<&str, usize> = HashMap::new();
+
+        for route in &self.routes {
+            let i = *index.entry(route.line_id()).or_insert_with(|| {
+                lines.push(Line {
+                    id: route.line_id().to_owned(),
+                    short_name: route.route_short_name.clone(),
+                    long_name: route.route_long_name.clone(),
+                    tts_name: route.route_long_name.clone(),
+                    color: hex_color(route.route_color.as_deref(), "#3D3D3D"),
+                    text_color: hex_color(route.route_text_color.as_deref(), "#FFFFFF"),
+                    ..Default::default()
+                });
+                lines.len() - 1
+            });
+            lines[i].route_ids.push(route.route_id.clone());
+        }
+
+        let routes: HashMap<&str, &str> = self
+            .routes
+            .iter()
+            .map(|r| (r.route_id.as_str(), r.line_id()))
+            .collect();
+        let mut patterns: BTreeSet<(&str, String)> = BTreeSet::new();
+        for trip in &self.trips {
+            if let Some(line_id) = routes.get(trip.route_id.as_str()) {
+                patterns.insert((line_id, trip.pattern_id()));
+            }
+        }
+        for (line_id, pattern_id) in patterns {
+            lines[index[line_id]].pattern_ids.push(pattern_id);
+        }
+
+        lines
+    }
+
+    /// Patterns with their path taken from the first of their trips.
+    ///
+    /// As in the API, trips of a pattern that share their service and stop
+    /// times form one [`PatternTrip`], which carries the service dates. For
+    /// departures, [`GtfsFeed::timetable`] is much cheaper.
+    pub fn to_patterns(&self) -> Vec<Pattern> {
+        let routes: HashMap<&str, &GtfsRoute> = self
+            .routes
+            .iter()
+            .map(|r| (r.route_id.as_str(), r))
+            .collect();
+        let stop_times = self.stop_times_by_trip();
+        let services = self.services();
+
+        let mut patterns: Vec<Pattern> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut groups: HashMap<TripGroup, usize> = HashMap::new();
+
+        for trip in &self.trips {
+            let times = stop_times
+                .get(trip.trip_id.as_str())
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let pattern_id = trip.pattern_id();
+
+            let i = match index.get(&pattern_id) {
+                Some(&i) => i,
+                None => {
+                    let route = routes.get(trip.route_id.as_str());
+                    patterns.push(Pattern {
+                        id: pattern_id.clone(),
+                        line_id: route
+                            .map_or(trip.route_id.as_str(), |r| r.line_id())
+                            .to_owned(),
+                        route_id: trip.route_id.clone(),
+                        shape_id: trip.shape_id.clone().unwrap_or_default(),
+                        short_name: route
+                            .map(|r| r.route_short_name.clone())
+                            .unwrap_or_default(),
+                        headsign: trip.trip_headsign.clone().unwrap_or_default(),
+                        direction_id: trip.direction_id.unwrap_or_default(),
+                        color: hex_color(route.and_then(|r| r.route_color.as_deref()), "#3D3D3D"),
+                        text_color: hex_color(
+                            route.and_then(|r| r.route_text_color.as_deref()),
+                            "#FFFFFF",
+                        ),
+                        path: path_of(times),
+                        ..Default::default()
+                    });
+                    index.insert(pattern_id, patterns.len() - 1);
+                    patterns.len() - 1
+                }
+            };
+
+            let key = (
+                i,
+                trip.service_id.as_str(),
+                times
+                    .iter()
+                    .map(|st| (&*st.stop_id, &*st.arrival_time))
+                    .collect(),
+            );
+            if let Some(&j) = groups.get(&key) {
+                patterns[i].trips[j].trip_ids.push(trip.trip_id.clone());
+                continue;
+            }
+            groups.insert(key, patterns[i].trips.len());
+
+            patterns[i].trips.push(PatternTrip {
+                service_id: trip.service_id.clone(),
+                trip_ids: vec![trip.trip_id.clone()],
+                dates: services
+                    .get(trip.service_id.as_str())
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .map(gtfs_date)
+                    .collect(),
+                schedule: times
+                    .iter()
+                    .map(|st| ScheduledStop {
+                        stop_id: st.stop_id.to_string(),
+                        stop_sequence: st.stop_sequence,
+                        arrival_time: st.arrival_time.to_string(),
+                        travel_time: None,
+                    })
+                    .collect(),
+            });
+        }
+
+        patterns
+    }
+
+    /// A [`Timetable`] of every trip in the feed, holding the dates of
+    /// each service once.
+    pub fn timetable(&self) -> Timetable {
+        let routes: HashMap<&str, &GtfsRoute> = self
+            .routes
+            .iter()
+            .map(|r| (r.route_id.as_str(), r))
+            .collect();
+        let stop_times = self.stop_times_by_trip();
+
+        let mut timetable = Timetable::default();
+        let mut services: HashMap<&str, usize> = self
+            .services()
+            .into_iter()
+            .map(|(id, dates)| (id, timetable.add_service(dates.into_iter().collect())))
+            .collect();
+
+        for trip in &self.trips {
+            let service = *services
+                .entry(trip.service_id.as_str())
+                .or_insert_with(|| timetable.add_service(Vec::new()));
+            let line_id = routes
+                .get(trip.route_id.as_str())
+                .map_or(trip.route_id.as_str(), |r| r.line_id());
+            timetable.add_trip(
+                Trip {
+                    trip_id: Some(trip.trip_id.clone()),
+                    pattern_id: trip.pattern_id(),
+                    route_id: trip.route_id.clone(),
+                    line_id: line_id.parse().unwrap_or_default(),
+                    headsign: trip.trip_headsign.clone().unwrap_or_default(),
+                    service,
+                },
+                stop_times
+                    .get(trip.trip_id.as_str())
+                    .into_iter()
+                    .flatten()
+                    .map(|st| (&*st.stop_id, st.stop_sequence, &*st.arrival_time)),
+            );
+        }
+        timetable.sort();
+        timetable
+    }
+
+    pub fn to_shapes(&self) -> Vec<Shape> {
+        let mut shapes: HashMap<&str, Vec<ShapePoint>> = HashMap::new();
+        for point in &self.shapes {
+            shapes
+                .entry(point.shape_id.as_str())
+                .or_default()
+                .push(ShapePoint {
+                    shape_pt_lat: point.shape_pt_lat,
+                    shape_pt_lon: point.shape_pt_lon,
+                    shape_pt_sequence: point.shape_pt_sequence,
+                    shape_dist_traveled: point.shape_dist_traveled.unwrap_or_default(),
+                });
+        }
+
+        let mut shapes: Vec<Shape> = shapes
+            .into_iter()
+            .map(|(id, mut points)| {
+                points.sort_by_key(|p| p.shape_pt_sequence);
+                Shape {
+                    id: id.to_owned(),
+                    extension: points.last().map_or(0.0, |p| p.shape_dist_traveled),
+                    points,
+                }
+            })
+            .collect();
+        shapes.sort_by(|a, b| a.id.cmp(&b.id));
+        shapes
+    }
+}
+
+/// Pattern index, service id and `(stop_id, arrival_time)` of every stop.
+type TripGroup<'a> = (usize, &'a str, Vec<(&'a str, &'a str)>);
+
+fn path_of(times: &[&GtfsStopTime]) -> Vec<PathStop> {
+    let mut previous = 0.0;
+    times
+        .iter()
+        .map(|st| {
+            let traveled = st.shape_dist_traveled.unwrap_or_default();
+            let delta = traveled - previous;
+            previous = traveled;
+            PathStop {
+                stop_id: st.stop_id.to_string(),
+                stop_sequence: st.stop_sequence,
+                allow_pickup: st.pickup_type != Some(1),
+                allow_drop_off: st.drop_off_type != Some(1),
+                distance_delta: delta,
+                shape_dist_traveled: traveled,
+            }
+        })
+        .collect()
+}
+
+fn gtfs_date(date: ServiceDate) -> String {
+    let (year, month, day) = date.ymd();
+    format!("{year:04}{month:02}{day:02}")
+}
+
+fn hex_color(color: Option<&str>, fallback: &str) -> String {
+    match color {
+        Some(c) if !c.is_empty() => format!("#{}", c.trim_start_matches('#')),
+        _ => fallback.to_owned(),
+    }
+}
+
+fn open_table<'a, R: Read + Seek>(
+    zip: &'a mut zip::ZipArchive<R>,
+    name: &str,
+    required: bool,
+) -> Result<Option<csv::Reader<zip::read::ZipFile<'a, R>>>, Error> {
+    match zip.by_name(name) {
+        Ok(file) => Ok(Some(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(file),
+        )),
+        Err(zip::result::ZipError::FileNotFound) if !required => Ok(None),
+        Err(e) => Err(Error::Io(format!("{name}: {e}"))),
+    }
+}
+
+fn table_error(name: &str, e: csv::Error) -> Error {
+    Error::Decode {
+        path: match e.position() {
+            Some(position) => format!("{name}:{}", position.line()),
+            None => name.to_owned(),
+        },
+        message: e.to_string(),
+    }
+}
+
+fn read_table<R: Read + Seek, T: DeserializeOwned>(
+    zip: &mut zip::ZipArchive<R>,
+    name: &str,
+    required: bool,
+) -> Result<Vec<T>, Error> {
+    let Some(mut reader) = open_table(zip, name, required)? else {
+        return Ok(Vec::new());
+    };
+    reader
+        .deserialize()
+        .map(|row| row.map_err(|e| table_error(name, e)))
+        .collect()
+}
+
+/// Reads `stop_times.txt` a record at a time, allocating each distinct id
+/// and time once.
+fn read_stop_times<R: Read + Seek>(
+    zip: &mut zip::ZipArchive<R>,
+) -> Result<Vec<GtfsStopTime>, Error> {
+    const NAME: &str = "stop_times.txt";
+    let Some(mut reader) = open_table(zip, NAME, true)? else {
+        return Ok(Vec::new());
+    };
+    let headers = reader.headers().map_err(|e| table_error(NAME, e))?.clone();
+
+    let mut shared: HashSet<Arc<str>> = HashSet::new();
+    let mut share = |s: &str| match shared.get(s) {
+        Some(s) => Arc::clone(s),
+        None => {
+            let s: Arc<str> = s.into();
+            shared.insert(Arc::clone(&s));
+            s
+        }
+    };
+
+    let mut stop_times = Vec::new();
+    let mut record = csv::StringRecord::new();
+    while reader
+        .read_record(&mut record)
+        .map_err(|e| table_error(NAME, e))?
+    {
+        let row: StopTimeRow<'_> = record
+            .deserialize(Some(&headers))
+            .map_err(|e| table_error(NAME, e))?;
+        stop_times.push(GtfsStopTime {
+            trip_id: share(row.trip_id),
+            arrival_time: share(row.arrival_time),
+            stop_id: share(row.stop_id),
+            stop_sequence: row.stop_sequence,
+            pickup_type: row.pickup_type,
+            drop_off_type: row.drop_off_type,
+            shape_dist_traveled: row.shape_dist_traveled,
+        });
+    }
+    Ok(stop_times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn feed_zip(files: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        let mut cursor = zip.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    const FEED: &[(&str, &str)] = &[
+        (
+            "stops.txt",
+            "stop_id,stop_name,tts_stop_name,stop_lat,stop_lon,municipality_id,wheelchair_boarding\n\
+             020387,Saldanha,Avenida da República,38.7363,-9.1389,1106,1\n\
+             140012,Almada,,38.6786,-9.1633,1503,0\n",
+        ),
+        (
+            "routes.txt",
+            "line_id,route_id,route_short_name,route_long_name,route_type,route_color,route_text_color\n\
+             1001,1001_0,1001,Almada - Lisboa,3,C61D23,FFFFFF\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,pattern_id,service_id,trip_id,trip_headsign,direction_id,shape_id\n\
+             1001_0,1001_0_1,WKD,t1,Almada,0,s1\n\
+             1001_0,1001_0_1,WKD,t2,Almada,0,s1\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence,shape_dist_traveled\n\
+             t1,08:00:00,08:00:00,020387,1,0\n\
+             t1,08:20:00,08:20:00,140012,2,5200\n\
+             t2,24:10:00,24:10:00,020387,1,0\n\
+             t2,24:30:00,24:30:00,140012,2,5200\n",
+        ),
+        (
+            "calendar_dates.txt",
+            "service_id,date,exception_type\n\
+             WKD,20260105,1\n\
+             WKD,20260106,1\n\
+             WKD,20260106,2\n",
+        ),
+    ];
+
+    #[test]
+    fn maps_a_feed_onto_api_types() {
+        let feed = GtfsFeed::from_zip(feed_zip(FEED)).unwrap();
+        assert!(feed.shapes.is_empty());
+
+        let stops = feed.to_stops();
+        assert_eq!(stops[0].tts_name, "Avenida da República");
+        assert_eq!(stops[1].tts_name, "Almada");
+        assert!(stops[0].wheelchair_boarding);
+        assert_eq!(stops[0].line_ids, ["1001"]);
+        assert_eq!(stops[0].pattern_ids, ["1001_0_1"]);
+
+        let lines = feed.to_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].color, "#C61D23");
+        assert_eq!(lines[0].pattern_ids, ["1001_0_1"]);
+
+        let patterns = feed.to_patterns();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].path[1].distance_delta, 5200.0);
+        assert_eq!(patterns[0].trips.len(), 2);
+        assert_eq!(patterns[0].trips[1].schedule[0].arrival_time, "24:10:00");
+        assert_eq!(patterns[0].trips[0].dates, ["20260105"]);
+    }
+
+    #[test]
+    fn expands_weekly_calendars() {
+        let mut files = FEED.to_vec();
+        files.push((
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+             WKD,1,1,1,1,1,0,0,20260101,20260111\n",
+        ));
+        let feed = GtfsFeed::from_zip(feed_zip(&files)).unwrap();
+        // Thursday to Sunday week after, without the removed Tuesday.
+        assert_eq!(
+            feed.service_dates("WKD"),
+            [
+                "20260101", "20260102", "20260105", "20260107", "20260108", "20260109"
+            ]
+        );
+
+        // 2026-01-07 08:00 UTC, a Wednesday in winter.
+        let timetable = feed.timetable();
+        let next = timetable.next_departures("020387", 1_767_744_000 + 7 * 3600, 2);
+        let trips: Vec<_> = next.iter().map(|a| a.trip_id.as_deref().unwrap()).collect();
+        assert_eq!(trips, ["t1", "t2"]);
+        assert_eq!(next[0].line_id, 1001);
+    }
+
+    #[test]
+    fn reports_the_offending_row() {
+        let mut files = FEED.to_vec();
+        files[0] = (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon\n1,A,x,0\n",
+        );
+        let err = GtfsFeed::from_zip(feed_zip(&files)).unwrap_err();
+        assert!(matches!(err, Error::Decode { ref path, .. } if path == "stops.txt:2"));
+    }
+
+    #[test]
+    fn shares_the_ids_of_stop_times() {
+        let feed = GtfsFeed::from_zip(feed_zip(FEED)).unwrap();
+        let [first, second, third, _] = feed.stop_times.as_slice() else {
+            panic!("expected four stop times");
+        };
+        assert!(Arc::ptr_eq(&first.trip_id, &second.trip_id));
+        assert!(Arc::ptr_eq(&first.stop_id, &third.stop_id));
+        assert_eq!(&*second.arrival_time, "08:20:00");
+        assert_eq!(second.shape_dist_traveled, Some(5200.0));
+
+        let mut files = FEED.to_vec();
+        files[3] = (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             t1,08:00:00,08:00:00,020387,1\n\
+             t1,08:20:00,08:20:00,140012,second\n",
+        );
+        let err = GtfsFeed::from_zip(feed_zip(&files)).unwrap_err();
+        assert!(matches!(err, Error::Decode { ref path, .. } if path == "stop_times.txt:3"));
+    }
+}
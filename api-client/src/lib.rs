@@ -1,4 +1,23 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 extern crate alloc;
 pub mod api;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod cassette;
+pub mod error;
+pub mod geojson;
+#[cfg(feature = "gtfs")]
+pub mod gtfs;
+#[cfg(feature = "gtfs-rt")]
+pub mod gtfs_rt;
+pub mod retry;
+pub mod search;
+pub mod spatial;
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod throttle;
+pub mod timetable;
 pub mod types;
+
+pub use error::Error;
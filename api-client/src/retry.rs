@@ -0,0 +1,407 @@
+//! Retrying decorator for any [`CarrisAPI`] backend.
+//!
+//! Transient failures (transport errors, timeouts, configured HTTP statuses
+//! and rate limiting) are retried with exponential backoff and jitter. A
+//! `Retry-After` sent with a 429 or a retried status such as 503 replaces
+//! the computed backoff.
+
+use crate::Error;
+use crate::types::{
+    Alert, Arrival, CarrisAPI, District, FromTransport, Line, Municipality, Pattern, Region, Shape,
+    Stop, Vehicle,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::time::Duration;
+use rand::rngs::SmallRng;
+use rand::{RngExt, SeedableRng};
+
+/// The timer the retry loop waits on between attempts.
+pub trait Timer {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()>;
+
+    /// A clock reading used to seed the jitter, so clients started together
+    /// do not retry in lockstep.
+    fn now_micros(&self) -> u64;
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioTimer;
+
+#[cfg(feature = "std")]
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> {
+        tokio::time::sleep(duration)
+    }
+
+    fn now_micros(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "embedded")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EmbassyTimer;
+
+#[cfg(feature = "embedded")]
+impl Timer for EmbassyTimer {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> {
+        embassy_time::Timer::after(embassy_time::Duration::from_micros(
+            duration.as_micros() as u64
+        ))
+    }
+
+    fn now_micros(&self) -> u64 {
+        embassy_time::Instant::now().as_micros()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Backoff before the second attempt; doubled for every further one.
+    pub base_delay: Duration,
+    /// Upper bound for a single wait. A `Retry-After` longer than this is
+    /// not waited for and the error is returned instead.
+    pub max_delay: Duration,
+    /// Fraction of the backoff that is randomised, between 0 and 1.
+    pub jitter: f32,
+    /// HTTP statuses worth retrying. Transport errors, timeouts and 429 are
+    /// always retried.
+    pub retry_on_status: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            retry_on_status: vec![500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn should_retry(&self, error: &Error) -> bool {
+        match error {
+            Error::Transport(_) | Error::Timeout | Error::RateLimited { .. } => true,
+            Error::Status { status, .. } => self.retry_on_status.contains(status),
+            _ => false,
+        }
+    }
+
+    /// How long to wait after the `attempt`-th attempt (starting at 0)
+    /// failed with `error`.
+    pub fn delay(&self, attempt: u32, error: &Error, seed: u64) -> Duration {
+        if let Error::RateLimited {
+            retry_after_secs: Some(secs),
+        }
+        | Error::Status {
+            retry_after_secs: Some(secs),
+            ..
+        } = error
+        {
+            return Duration::from_secs(*secs);
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+            .min(self.max_delay);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        let factor = SmallRng::seed_from_u64(seed).random_range(1.0 - jitter..=1.0);
+        backoff.mul_f32(factor)
+    }
+}
+
+/// Wraps a [`CarrisAPI`] and retries failed requests according to a
+/// [`RetryPolicy`].
+#[derive(Debug)]
+pub struct Retry<A, T> {
+    inner: A,
+    policy: RetryPolicy,
+    timer: T,
+}
+
+impl<A, T: Timer> Retry<A, T> {
+    pub fn new(inner: A, policy: RetryPolicy, timer: T) -> Self {
+        Self {
+            inner,
+            policy,
+            timer,
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    async fn run<'s, R, Fut>(&'s self, mut call: impl FnMut(&'s A) -> Fut) -> Result<R, Error>
+    where
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let error = match call(&self.inner).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            if attempt + 1 >= self.policy.max_attempts || !self.policy.should_retry(&error) {
+                return Err(error);
+            }
+
+            let delay = self.policy.delay(attempt, &error, self.timer.now_micros());
+            if delay > self.policy.max_delay {
+                return Err(error);
+            }
+
+            self.timer.sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl<A, T> FromTransport for Retry<A, T>
+where
+    A: FromTransport,
+    T: Timer + Default,
+{
+    type Transport = A::Transport;
+
+    fn from_transport(transport: Self::Transport) -> Self {
+        Self::new(
+            A::from_transport(transport),
+            RetryPolicy::default(),
+            T::default(),
+        )
+    }
+
+    fn from_transport_with_base_url(transport: Self::Transport, base_url: &str) -> Self {
+        Self::new(
+            A::from_transport_with_base_url(transport, base_url),
+            RetryPolicy::default(),
+            T::default(),
+        )
+    }
+}
+
+impl<A, T> CarrisAPI for Retry<A, T>
+where
+    A: CarrisAPI,
+    T: Timer + Default,
+{
+    async fn arrivals_by_stop(&self, stop: &str) -> Result<Vec<Arrival>, Error> {
+        self.run(|api| api.arrivals_by_stop(stop)).await
+    }
+
+    async fn arrivals_by_pattern(&self, pattern_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.run(|api| api.arrivals_by_pattern(pattern_id)).await
+    }
+
+    async fn arrivals_by_trip(&self, trip_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.run(|api| api.arrivals_by_trip(trip_id)).await
+    }
+
+    async fn get_all_stops(&self) -> Result<Vec<Stop>, Error> {
+        self.run(|api| api.get_all_stops()).await
+    }
+
+    async fn get_all_lines(&self) -> Result<Vec<Line>, Error> {
+        self.run(|api| api.get_all_lines()).await
+    }
+
+    async fn get_line(&self, line_id: &str) -> Result<Line, Error> {
+        self.run(|api| api.get_line(line_id)).await
+    }
+
+    async fn get_pattern(&self, pattern_id: &str) -> Result<Pattern, Error> {
+        self.run(|api| api.get_pattern(pattern_id)).await
+    }
+
+    async fn get_shape(&self, shape_id: &str) -> Result<Shape, Error> {
+        self.run(|api| api.get_shape(shape_id)).await
+    }
+
+    async fn get_all_vehicles(&self) -> Result<Vec<Vehicle>, Error> {
+        self.run(|api| api.get_all_vehicles()).await
+    }
+
+    async fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        self.run(|api| api.get_municipalities()).await
+    }
+
+    async fn get_districts(&self) -> Result<Vec<District>, Error> {
+        self.run(|api| api.get_districts()).await
+    }
+
+    async fn get_regions(&self) -> Result<Vec<Region>, Error> {
+        self.run(|api| api.get_regions()).await
+    }
+
+    async fn get_alerts(&self) -> Result<Vec<Alert>, Error> {
+        self.run(|api| api.get_alerts()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            max_delay: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let delays: Vec<_> = (0..4)
+            .map(|attempt| policy.delay(attempt, &Error::Timeout, 0))
+            .collect();
+        assert_eq!(
+            delays,
+            [250, 500, 1000, 1000].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds_and_retry_after_wins() {
+        let policy = RetryPolicy::default();
+        for seed in 0..100 {
+            let d = policy.delay(
+                1,
+                &Error::Status {
+                    status: 502,
+                    retry_after_secs: None,
+                },
+                seed,
+            );
+            assert!(d >= Duration::from_millis(250) && d <= Duration::from_millis(500));
+        }
+
+        let limited = Error::RateLimited {
+            retry_after_secs: Some(7),
+        };
+        assert_eq!(policy.delay(0, &limited, 0), Duration::from_secs(7));
+
+        let unavailable = Error::from_status(503, crate::api::Endpoint::Lines, Some(4));
+        assert!(policy.should_retry(&unavailable));
+        assert_eq!(policy.delay(0, &unavailable, 0), Duration::from_secs(4));
+        assert!(policy.should_retry(&limited));
+        assert!(!policy.should_retry(&Error::StopNotFound("1".into())));
+    }
+
+    /// Fails with the queued errors, then succeeds with the call count.
+    struct Flaky {
+        errors: RefCell<Vec<Error>>,
+        calls: Cell<u32>,
+    }
+
+    impl Flaky {
+        fn new(mut errors: Vec<Error>) -> Self {
+            errors.reverse();
+            Self {
+                errors: RefCell::new(errors),
+                calls: Cell::new(0),
+            }
+        }
+
+        async fn call(&self) -> Result<u32, Error> {
+            self.calls.set(self.calls.get() + 1);
+            match self.errors.borrow_mut().pop() {
+                Some(error) => Err(error),
+                None => Ok(self.calls.get()),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingTimer {
+        slept: RefCell<Vec<Duration>>,
+    }
+
+    impl Timer for RecordingTimer {
+        fn sleep(&self, duration: Duration) -> impl Future<Output = ()> {
+            self.slept.borrow_mut().push(duration);
+            core::future::ready(())
+        }
+
+        fn now_micros(&self) -> u64 {
+            0
+        }
+    }
+
+    fn retry(errors: Vec<Error>) -> Retry<Flaky, RecordingTimer> {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..Default::default()
+        };
+        Retry::new(Flaky::new(errors), policy, RecordingTimer::default())
+    }
+
+    fn bad_gateway() -> Error {
+        Error::Status {
+            status: 502,
+            retry_after_secs: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_until_an_attempt_succeeds() {
+        let retry = retry(vec![bad_gateway(), Error::Timeout]);
+        assert_eq!(retry.run(Flaky::call).await, Ok(3));
+        assert_eq!(
+            *retry.timer.slept.borrow(),
+            [250, 500].map(Duration::from_millis)
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let retry = retry(vec![
+            bad_gateway(),
+            bad_gateway(),
+            Error::Timeout,
+            bad_gateway(),
+        ]);
+        assert_eq!(retry.run(Flaky::call).await, Err(Error::Timeout));
+        assert_eq!(retry.inner().calls.get(), 3);
+        assert_eq!(retry.timer.slept.borrow().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn returns_other_errors_at_once() {
+        let retry = retry(vec![Error::StopNotFound("020387".into())]);
+        assert_eq!(
+            retry.run(Flaky::call).await,
+            Err(Error::StopNotFound("020387".into()))
+        );
+        assert_eq!(retry.inner().calls.get(), 1);
+
+        // Waiting out a minute is not worth it when the cap is ten seconds.
+        let limited = Error::RateLimited {
+            retry_after_secs: Some(60),
+        };
+        let retry = self::retry(vec![limited.clone()]);
+        assert_eq!(retry.run(Flaky::call).await, Err(limited));
+        assert_eq!(retry.inner().calls.get(), 1);
+        assert!(retry.timer.slept.borrow().is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+//! Typo- and accent-tolerant stop search.
+//!
+//! [`StopSearch`] folds names to lowercase ASCII, so "Sé" and "Se" match,
+//! splits them into tokens and ranks stops by how well every query token
+//! matches: whole tokens before prefixes before substrings before tokens a
+//! typo or two away. Stop ids and municipality names are searched as well.
+
+use crate::types::{Municipality, Stop};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const EXACT: u32 = 100;
+const PREFIX: u32 = 80;
+const SUBSTRING: u32 = 50;
+const TYPO: u32 = 40;
+
+/// Lowercases `text` and strips the diacritics of Portuguese (and most
+/// other Latin) letters; anything not alphanumeric becomes a space.
+pub fn fold(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' | 'ª' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' | 'º' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            'ñ' => 'n',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect()
+}
+
+fn tokens(folded: &str) -> impl Iterator<Item = &str> {
+    folded.split(' ').filter(|t| !t.is_empty())
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    id: String,
+    /// Folded `long_name`.
+    name: String,
+    /// Tokens of the name and `tts_name`.
+    tokens: Vec<String>,
+    /// Tokens of the municipality name, which count for less.
+    municipality: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StopSearch {
+    entries: Vec<Entry>,
+}
+
+impl StopSearch {
+    pub fn new(stops: &[Stop], municipalities: &[Municipality]) -> Self {
+        let entries = stops
+            .iter()
+            .map(|stop| {
+                let name = fold(&stop.long_name);
+                let mut stop_tokens: Vec<String> = tokens(&name)
+                    .chain(tokens(&fold(&stop.tts_name)))
+                    .map(String::from)
+                    .collect();
+                stop_tokens.sort_unstable();
+                stop_tokens.dedup();
+
+                let municipality = municipalities
+                    .iter()
+                    .find(|m| Some(&m.id) == stop.municipality_id.as_ref())
+                    .map(|m| tokens(&fold(&m.name)).map(String::from).collect())
+                    .unwrap_or_default();
+
+                Entry {
+                    id: stop.id.clone(),
+                    name,
+                    tokens: stop_tokens,
+                    municipality,
+                }
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Ids of the stops matching every word of `query`, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&str> {
+        let folded = fold(query);
+        let query_tokens: Vec<&str> = tokens(&folded).collect();
+        if query_tokens.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+        let phrase = query_tokens.join(" ");
+
+        let mut hits: Vec<(u32, &Entry)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| Some((score(entry, &query_tokens, &phrase)?, entry)))
+            .collect();
+        hits.sort_by(|(a, x), (b, y)| {
+            b.cmp(a)
+                .then(x.name.len().cmp(&y.name.len()))
+                .then(x.name.cmp(&y.name))
+                .then(x.id.cmp(&y.id))
+        });
+        hits.into_iter()
+            .take(limit)
+            .map(|(_, entry)| entry.id.as_str())
+            .collect()
+    }
+}
+
+fn score(entry: &Entry, query_tokens: &[&str], phrase: &str) -> Option<u32> {
+    if entry.id == phrase {
+        return Some(10 * EXACT);
+    }
+    if query_tokens.len() == 1 && entry.id.starts_with(phrase) {
+        return Some(5 * EXACT);
+    }
+
+    let mut total = 0;
+    for query in query_tokens {
+        let name = best_match(&entry.tokens, query);
+        let municipality = best_match(&entry.municipality, query) / 2;
+        match name.max(municipality) {
+            0 => return None,
+            s => total += s,
+        }
+    }
+    if entry.name.starts_with(phrase) {
+        total += SUBSTRING;
+    }
+    Some(total)
+}
+
+fn best_match(tokens: &[String], query: &str) -> u32 {
+    tokens
+        .iter()
+        .map(|token| match_token(token, query))
+        .max()
+        .unwrap_or(0)
+}
+
+fn match_token(token: &str, query: &str) -> u32 {
+    if token == query {
+        return EXACT;
+    }
+    if token.starts_with(query) {
+        return PREFIX;
+    }
+    if query.len() >= 3 && token.contains(query) {
+        return SUBSTRING;
+    }
+
+    let allowed = match query.chars().count() {
+        0..=3 => return 0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    // Compare against the start of longer tokens, so a typo in a prefix
+    // still matches.
+    let end = token
+        .char_indices()
+        .nth(query.chars().count() + allowed)
+        .map_or(token.len(), |(i, _)| i);
+    match edit_distance(&token[..end], query, allowed) {
+        Some(d) => TYPO - 10 * d as u32,
+        None => 0,
+    }
+}
+
+/// Levenshtein distance between `a` and `b`, or `None` once it exceeds
+/// `max`. Also tries the prefixes of `a`, so `a` may be longer than `b`.
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut best = previous[b.len()];
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = Vec::with_capacity(b.len() + 1);
+        current.push(i + 1);
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        best = best.min(current[b.len()]);
+        if current.iter().all(|&d| d > max) {
+            break;
+        }
+        previous = current;
+    }
+
+    (best <= max).then_some(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(id: &str, name: &str, municipality: &str) -> Stop {
+        Stop {
+            id: id.into(),
+            long_name: name.into(),
+            tts_name: name.into(),
+            municipality_id: Some(municipality.into()),
+            ..Default::default()
+        }
+    }
+
+    fn index() -> StopSearch {
+        StopSearch::new(
+            &[
+                stop("060001", "Sé", "1106"),
+                stop("020387", "Av. República (Saldanha)", "1106"),
+                stop("140012", "Almada (Centro Sul)", "1503"),
+                stop("140013", "Rua Cândido dos Reis", "1503"),
+                stop("060002", "Largo da Sé", "1106"),
+            ],
+            &[
+                Municipality {
+                    id: "1106".into(),
+                    name: "Lisboa".into(),
+                    ..Default::default()
+                },
+                Municipality {
+                    id: "1503".into(),
+                    name: "Almada".into(),
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn folds_portuguese_diacritics() {
+        assert_eq!(fold("Sé"), "se");
+        assert_eq!(fold("Cândido dos Reis, 1º"), "candido dos reis  1o");
+        assert_eq!(fold("CAÇADORES"), "cacadores");
+    }
+
+    #[test]
+    fn ranks_and_limits_matches() {
+        let index = index();
+        assert_eq!(index.search("se", 10), ["060001", "060002"]);
+        assert_eq!(index.search("Se", 1), ["060001"]);
+        assert_eq!(index.search("republica", 10), ["020387"]);
+        assert_eq!(index.search("repulbica", 10), ["020387"]);
+        assert_eq!(index.search("020387", 10), ["020387"]);
+        assert_eq!(index.search("14001", 10), ["140012", "140013"]);
+        // "almada" names one stop and the municipality of both.
+        assert_eq!(index.search("almada", 10), ["140012", "140013"]);
+        assert_eq!(index.search("reis almada", 10), ["140013"]);
+        assert!(index.search("   ", 10).is_empty());
+        assert_eq!(index.search("xyzzy", 10), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,225 @@
+//! Finding stops near a position.
+//!
+//! [`StopIndex`] buckets stops into a grid of cells a few hundred metres
+//! wide and keeps them sorted by cell, so a query only measures the stops of
+//! the cells its circle overlaps. The index is a pair of flat vectors of
+//! positions and indices, twelve bytes a stop, and works without std;
+//! queries answer with indices into the caller's stops.
+
+use crate::types::Stop;
+use alloc::vec::Vec;
+
+/// Mean earth radius in metres.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Metres per degree of latitude.
+const METERS_PER_DEGREE: f64 = EARTH_RADIUS * core::f64::consts::PI / 180.0;
+
+/// Cell size in degrees, about 550 m north to south and 430 m east to west
+/// around Lisbon.
+const CELL_DEGREES: f64 = 0.005;
+
+/// Great-circle distance in metres between two WGS84 positions.
+pub fn haversine_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = libm::sin(dlat / 2.0).powi(2)
+        + libm::cos(lat1) * libm::cos(lat2) * libm::sin(dlon / 2.0).powi(2);
+    2.0 * EARTH_RADIUS * libm::asin(libm::sqrt(a.min(1.0)))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    lat: f32,
+    lon: f32,
+    /// Index of the stop in the slice the index was built from.
+    index: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    row: i32,
+    col: i32,
+    /// Index of the cell's first entry.
+    start: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StopIndex {
+    /// Sorted by cell.
+    entries: Vec<Entry>,
+    /// Sorted by row, then column.
+    cells: Vec<Cell>,
+}
+
+impl StopIndex {
+    /// Indexes `stops`; queries return indices into this slice.
+    pub fn new(stops: &[Stop]) -> Self {
+        Self::from_positions(stops.iter().map(|s| (s.lat, s.lon)))
+    }
+
+    /// Indexes `(lat, lon)` positions; queries return their position in
+    /// the iterator.
+    pub fn from_positions(positions: impl IntoIterator<Item = (f64, f64)>) -> Self {
+        let mut entries: Vec<Entry> = positions
+            .into_iter()
+            .enumerate()
+            .map(|(index, (lat, lon))| Entry {
+                lat: lat as f32,
+                lon: lon as f32,
+                index: index as u32,
+            })
+            .collect();
+        entries.sort_by_key(|e| cell_of(e.lat.into(), e.lon.into()));
+
+        let mut cells: Vec<Cell> = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let (row, col) = cell_of(entry.lat.into(), entry.lon.into());
+            if cells.last().is_none_or(|c| (c.row, c.col) != (row, col)) {
+                cells.push(Cell {
+                    row,
+                    col,
+                    start: i as u32,
+                });
+            }
+        }
+
+        Self { entries, cells }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Indices of the stops within `meters` of the position with their
+    /// distance, nearest first.
+    pub fn within_radius(&self, lat: f64, lon: f64, meters: f64) -> Vec<(usize, f64)> {
+        let (Some(first), Some(last)) = (self.cells.first(), self.cells.last()) else {
+            return Vec::new();
+        };
+
+        let dlat = meters / METERS_PER_DEGREE;
+        let dlon = dlat / libm::cos(lat.to_radians()).max(0.01);
+        let (min_row, min_col) = cell_of(lat - dlat, lon - dlon);
+        let (max_row, max_col) = cell_of(lat + dlat, lon + dlon);
+
+        let mut found = Vec::new();
+        for row in min_row.max(first.row)..=max_row.min(last.row) {
+            let mut i = self
+                .cells
+                .partition_point(|c| (c.row, c.col) < (row, min_col));
+            while let Some(cell) = self
+                .cells
+                .get(i)
+                .filter(|c| c.row == row && c.col <= max_col)
+            {
+                let end = self
+                    .cells
+                    .get(i + 1)
+                    .map_or(self.entries.len(), |c| c.start as usize);
+                found.extend(
+                    self.entries[cell.start as usize..end]
+                        .iter()
+                        .map(|e| {
+                            let distance = haversine_meters(lat, lon, e.lat.into(), e.lon.into());
+                            (e.index as usize, distance)
+                        })
+                        .filter(|(_, distance)| *distance <= meters),
+                );
+                i += 1;
+            }
+        }
+
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    /// Indices of the `k` stops nearest to the position with their
+    /// distance, nearest first.
+    pub fn nearest(&self, lat: f64, lon: f64, k: usize) -> Vec<(usize, f64)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        // Widen the circle until it holds k stops; every stop outside it is
+        // further away than those inside.
+        let mut meters = 500.0;
+        loop {
+            let mut found = self.within_radius(lat, lon, meters);
+            if found.len() >= k || found.len() == self.entries.len() || meters > EARTH_RADIUS * 4.0
+            {
+                found.truncate(k);
+                return found;
+            }
+            meters *= 2.0;
+        }
+    }
+}
+
+fn cell_of(lat: f64, lon: f64) -> (i32, i32) {
+    (
+        libm::floor(lat / CELL_DEGREES) as i32,
+        libm::floor(lon / CELL_DEGREES) as i32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn stop(id: &str, lat: f64, lon: f64) -> Stop {
+        Stop {
+            id: id.into(),
+            lat,
+            lon,
+            ..Default::default()
+        }
+    }
+
+    fn stops() -> Vec<Stop> {
+        vec![
+            stop("almada", 38.6786, -9.1633),
+            stop("saldanha", 38.7363, -9.1451),
+            stop("picoas", 38.7306, -9.1469),
+            stop("marques", 38.7253, -9.1500),
+            stop("setubal", 38.5244, -8.8882),
+        ]
+    }
+
+    fn ids<'a>(stops: &'a [Stop], found: &[(usize, f64)]) -> Vec<&'a str> {
+        found.iter().map(|(i, _)| stops[*i].id.as_str()).collect()
+    }
+
+    #[test]
+    fn measures_great_circle_distances() {
+        // Saldanha to Marquês de Pombal is about 1.3 km.
+        let d = haversine_meters(38.7363, -9.1451, 38.7253, -9.1500);
+        assert!((d - 1_300.0).abs() < 50.0, "{d}");
+        assert_eq!(haversine_meters(38.7, -9.1, 38.7, -9.1), 0.0);
+    }
+
+    #[test]
+    fn finds_stops_by_radius_and_rank() {
+        let stops = stops();
+        let index = StopIndex::new(&stops);
+        assert_eq!(
+            ids(&stops, &index.within_radius(38.7363, -9.1451, 1_000.0)),
+            ["saldanha", "picoas"]
+        );
+        assert_eq!(
+            ids(&stops, &index.nearest(38.7300, -9.1470, 3)),
+            ["picoas", "marques", "saldanha"]
+        );
+        // Positions are kept as f32, which is good to a metre.
+        let (_, distance) = index.nearest(38.7363, -9.1451, 1)[0];
+        assert!(distance < 1.0, "{distance}");
+        assert_eq!(index.nearest(38.0, -9.0, 10).len(), 5);
+        assert!(StopIndex::default().nearest(38.0, -9.0, 1).is_empty());
+    }
+}
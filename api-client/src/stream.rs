@@ -0,0 +1,239 @@
+//! Incremental parsing of JSON array responses.
+//!
+//! The embedded client cannot hold a whole `/arrivals/by_stop` body in RAM,
+//! so responses are fed through an [`ArraySplitter`] chunk by chunk and each
+//! element is deserialized as soon as it is complete. Only the element being
+//! assembled needs to fit in the caller's buffer.
+
+use crate::types::{Arrival, best_arrival_unix};
+use alloc::vec::Vec;
+
+#[derive(Debug, PartialEq)]
+pub enum StreamError<E> {
+    /// A single array element did not fit into the element buffer.
+    TooLarge,
+    /// The input is not a JSON array of objects or arrays.
+    Malformed,
+    /// The element callback failed.
+    Item(E),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    BeforeArray,
+    BetweenElements,
+    InElement,
+    Done,
+}
+
+/// Splits a JSON array into its top-level elements without buffering the
+/// whole document.
+#[derive(Debug)]
+pub struct ArraySplitter {
+    state: State,
+    depth: u32,
+    in_string: bool,
+    escaped: bool,
+    len: usize,
+}
+
+impl Default for ArraySplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArraySplitter {
+    pub fn new() -> Self {
+        Self {
+            state: State::BeforeArray,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            len: 0,
+        }
+    }
+
+    /// Feeds the next chunk of the document.
+    ///
+    /// Bytes of the element being assembled are copied into `buf`, and
+    /// `on_item` is called with each element as soon as its closing bracket
+    /// is seen. Chunks may split elements, strings and escapes anywhere.
+    pub fn feed<E>(
+        &mut self,
+        input: &[u8],
+        buf: &mut [u8],
+        mut on_item: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), StreamError<E>> {
+        for &b in input {
+            match self.state {
+                State::BeforeArray => match b {
+                    b'[' => self.state = State::BetweenElements,
+                    b if b.is_ascii_whitespace() => {}
+                    _ => return Err(StreamError::Malformed),
+                },
+                State::BetweenElements => match b {
+                    b'{' | b'[' => {
+                        self.state = State::InElement;
+                        self.depth = 1;
+                        self.len = 0;
+                        self.push(b, buf)?;
+                    }
+                    b']' => self.state = State::Done,
+                    b',' => {}
+                    b if b.is_ascii_whitespace() => {}
+                    _ => return Err(StreamError::Malformed),
+                },
+                State::InElement => {
+                    self.push(b, buf)?;
+                    if self.in_string {
+                        if self.escaped {
+                            self.escaped = false;
+                        } else if b == b'\\' {
+                            self.escaped = true;
+                        } else if b == b'"' {
+                            self.in_string = false;
+                        }
+                        continue;
+                    }
+                    match b {
+                        b'"' => self.in_string = true,
+                        b'{' | b'[' => self.depth += 1,
+                        b'}' | b']' => {
+                            self.depth -= 1;
+                            if self.depth == 0 {
+                                self.state = State::BetweenElements;
+                                on_item(&buf[..self.len]).map_err(StreamError::Item)?;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                State::Done => {
+                    if !b.is_ascii_whitespace() {
+                        return Err(StreamError::Malformed);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the document ended with the closing `]` of the array.
+    pub fn finish<E>(&self) -> Result<(), StreamError<E>> {
+        match self.state {
+            State::Done => Ok(()),
+            _ => Err(StreamError::Malformed),
+        }
+    }
+
+    fn push<E>(&mut self, b: u8, buf: &mut [u8]) -> Result<(), StreamError<E>> {
+        let slot = buf.get_mut(self.len).ok_or(StreamError::TooLarge)?;
+        *slot = b;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Keeps the `limit` soonest future arrivals out of a stream of arrivals.
+#[derive(Debug)]
+pub struct NextArrivals {
+    now_unix: i64,
+    limit: usize,
+    arrivals: Vec<Arrival>,
+}
+
+impl NextArrivals {
+    pub fn new(now_unix: i64, limit: usize) -> Self {
+        Self {
+            now_unix,
+            limit,
+            arrivals: Vec::with_capacity(limit),
+        }
+    }
+
+    pub fn push(&mut self, arrival: Arrival) {
+        if self.limit == 0 || !arrival.is_future(self.now_unix) {
+            return;
+        }
+
+        let at = best_arrival_unix(&arrival);
+        let pos = self
+            .arrivals
+            .partition_point(|a| best_arrival_unix(a) <= at);
+        if pos >= self.limit {
+            return;
+        }
+        if self.arrivals.len() == self.limit {
+            self.arrivals.pop();
+        }
+        self.arrivals.insert(pos, arrival);
+    }
+
+    /// The kept arrivals, soonest first.
+    pub fn into_vec(self) -> Vec<Arrival> {
+        self.arrivals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    fn split(input: &[u8], chunk: usize, buf_len: usize) -> Result<Vec<String>, StreamError<()>> {
+        let mut splitter = ArraySplitter::new();
+        let mut buf = alloc::vec![0u8; buf_len];
+        let mut items = Vec::new();
+        for part in input.chunks(chunk) {
+            splitter.feed(part, &mut buf, |item| {
+                items.push(String::from_utf8(item.to_vec()).unwrap());
+                Ok(())
+            })?;
+        }
+        splitter.finish()?;
+        Ok(items)
+    }
+
+    #[test]
+    fn splits_elements_across_chunk_boundaries() {
+        let input = br#" [ {"a":"x}]\"y","b":[1,{"c":2}]} , {"d":{}} ] "#;
+        for chunk in 1..input.len() {
+            assert_eq!(
+                split(input, chunk, 64).unwrap(),
+                [r#"{"a":"x}]\"y","b":[1,{"c":2}]}"#, r#"{"d":{}}"#],
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_elements_larger_than_the_buffer() {
+        assert_eq!(split(br#"[{"a":1}]"#, 4, 4), Err(StreamError::TooLarge));
+    }
+
+    #[test]
+    fn rejects_truncated_and_non_array_input() {
+        assert_eq!(split(br#"[{"a":1}"#, 4, 16), Err(StreamError::Malformed));
+        assert_eq!(split(br#"{"a":1}"#, 4, 16), Err(StreamError::Malformed));
+    }
+
+    fn arrival(scheduled: i64, estimated: Option<i64>) -> Arrival {
+        Arrival {
+            scheduled_arrival_unix: Some(scheduled),
+            estimated_arrival_unix: estimated,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_the_soonest_future_arrivals() {
+        let mut next = NextArrivals::new(100, 2);
+        next.push(arrival(50, None));
+        next.push(arrival(400, None));
+        next.push(arrival(200, Some(350)));
+        next.push(arrival(300, None));
+
+        let kept: Vec<_> = next.into_vec().iter().map(best_arrival_unix).collect();
+        assert_eq!(kept, [Some(300), Some(350)]);
+    }
+}
@@ -0,0 +1,408 @@
+//! Client-side rate limiting for std backends.
+//!
+//! [`Throttled`] takes a token from a shared [`TokenBucket`] before every
+//! request and lets concurrent identical requests share one in-flight call.
+
+use crate::Error;
+use crate::api::Endpoint;
+use crate::cache::{Conditional, Revalidate, Validators};
+use crate::types::{
+    Alert, Arrival, CarrisAPI, District, FromTransport, Line, Municipality, Pattern, Region, Shape,
+    Stop, Vehicle,
+};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Allows bursts of `capacity` requests, refilled at `per_second`.
+    pub fn new(capacity: u32, per_second: f64) -> Self {
+        Self {
+            capacity: capacity.max(1) as f64,
+            per_second,
+            state: Mutex::new(BucketState {
+                tokens: capacity.max(1) as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a token is available and takes it.
+    pub async fn acquire(&self) {
+        while let Err(wait) = self.try_take(Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Takes a token, or tells how long until the next one is available.
+    fn try_take(&self, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.updated).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.per_second).min(self.capacity);
+        state.updated = now;
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - state.tokens) / self.per_second,
+            ))
+        }
+    }
+}
+
+impl Default for TokenBucket {
+    /// Bursts of 10 requests, then 2 per second.
+    fn default() -> Self {
+        Self::new(10, 2.0)
+    }
+}
+
+/// Runs at most one request per key at a time; callers arriving while it
+/// is in flight get a copy of its result.
+struct Coalescer {
+    /// Senders of the leaders, by key and result type.
+    in_flight: Mutex<HashMap<(String, TypeId), Box<dyn Any + Send>>>,
+}
+
+type Sender<T> = broadcast::Sender<Result<T, Error>>;
+
+impl Coalescer {
+    fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn run<T: Clone + Send + 'static>(
+        &self,
+        key: String,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let key = (key, TypeId::of::<T>());
+        let waiting = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(leader) => Some(downcast::<T>(leader.as_ref()).subscribe()),
+                None => {
+                    in_flight.insert(key.clone(), Box::new(Sender::<T>::new(1)));
+                    None
+                }
+            }
+        };
+
+        if let Some(mut waiting) = waiting {
+            // The leader was cancelled without an answer, so ask ourselves.
+            return match waiting.recv().await {
+                Ok(result) => result,
+                Err(_) => request.await,
+            };
+        }
+
+        let leader = Leader {
+            coalescer: self,
+            key,
+            finished: false,
+        };
+        let result = request.await;
+        if let Some(sender) = leader.finish() {
+            let _ = downcast::<T>(sender.as_ref()).send(result.clone());
+        }
+        result
+    }
+}
+
+fn downcast<T: 'static>(sender: &(dyn Any + Send)) -> &Sender<T> {
+    sender
+        .downcast_ref()
+        .expect("senders are keyed by their result type")
+}
+
+impl fmt::Debug for Coalescer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let in_flight = self.in_flight.lock().unwrap();
+        f.debug_struct("Coalescer")
+            .field(
+                "in_flight",
+                &in_flight.keys().map(|(key, _)| key).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// Unregisters an in-flight request, also when its future is dropped.
+struct Leader<'c> {
+    coalescer: &'c Coalescer,
+    key: (String, TypeId),
+    finished: bool,
+}
+
+impl Leader<'_> {
+    fn finish(mut self) -> Option<Box<dyn Any + Send>> {
+        // Disarm the drop, which could otherwise remove a leader that
+        // registered the key since.
+        self.finished = true;
+        self.coalescer.in_flight.lock().unwrap().remove(&self.key)
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.coalescer.in_flight.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+/// Wraps a [`CarrisAPI`] and rate limits it with a [`TokenBucket`].
+///
+/// Concurrent identical requests share a single call to the API.
+#[derive(Debug)]
+pub struct Throttled<A> {
+    inner: A,
+    bucket: TokenBucket,
+    in_flight: Coalescer,
+}
+
+impl<A: CarrisAPI> Throttled<A> {
+    pub fn new(inner: A, bucket: TokenBucket) -> Self {
+        Self {
+            inner,
+            bucket,
+            in_flight: Coalescer::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Waits for a token, unless an identical request is already in flight
+    /// and can be shared.
+    async fn limited<T: Clone + Send + 'static>(
+        &self,
+        key: String,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        self.in_flight
+            .run(key, async {
+                self.bucket.acquire().await;
+                request.await
+            })
+            .await
+    }
+}
+
+impl<A: CarrisAPI + Revalidate> Revalidate for Throttled<A> {
+    async fn get_conditional(
+        &self,
+        endpoint: Endpoint<'_>,
+        validators: &Validators,
+    ) -> Result<Conditional, Error> {
+        let key = format!(
+            "{endpoint}|{}|{}",
+            validators.etag.as_deref().unwrap_or_default(),
+            validators.last_modified.as_deref().unwrap_or_default()
+        );
+        self.limited(key, self.inner.get_conditional(endpoint, validators))
+            .await
+    }
+}
+
+impl<A: CarrisAPI + FromTransport> FromTransport for Throttled<A> {
+    type Transport = A::Transport;
+
+    fn from_transport(transport: Self::Transport) -> Self {
+        Self::new(A::from_transport(transport), TokenBucket::default())
+    }
+
+    fn from_transport_with_base_url(transport: Self::Transport, base_url: &str) -> Self {
+        Self::new(
+            A::from_transport_with_base_url(transport, base_url),
+            TokenBucket::default(),
+        )
+    }
+}
+
+impl<A: CarrisAPI> CarrisAPI for Throttled<A> {
+    async fn arrivals_by_stop(&self, stop: &str) -> Result<Vec<Arrival>, Error> {
+        self.limited(
+            key(Endpoint::ArrivalsByStop(stop)),
+            self.inner.arrivals_by_stop(stop),
+        )
+        .await
+    }
+
+    async fn arrivals_by_pattern(&self, pattern_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.limited(
+            key(Endpoint::ArrivalsByPattern(pattern_id)),
+            self.inner.arrivals_by_pattern(pattern_id),
+        )
+        .await
+    }
+
+    async fn arrivals_by_trip(&self, trip_id: &str) -> Result<Vec<Arrival>, Error> {
+        self.limited(
+            key(Endpoint::ArrivalsByTrip(trip_id)),
+            self.inner.arrivals_by_trip(trip_id),
+        )
+        .await
+    }
+
+    async fn get_all_stops(&self) -> Result<Vec<Stop>, Error> {
+        self.limited(key(Endpoint::Stops), self.inner.get_all_stops())
+            .await
+    }
+
+    async fn get_all_lines(&self) -> Result<Vec<Line>, Error> {
+        self.limited(key(Endpoint::Lines), self.inner.get_all_lines())
+            .await
+    }
+
+    async fn get_line(&self, line_id: &str) -> Result<Line, Error> {
+        self.limited(key(Endpoint::Line(line_id)), self.inner.get_line(line_id))
+            .await
+    }
+
+    async fn get_pattern(&self, pattern_id: &str) -> Result<Pattern, Error> {
+        self.limited(
+            key(Endpoint::Pattern(pattern_id)),
+            self.inner.get_pattern(pattern_id),
+        )
+        .await
+    }
+
+    async fn get_shape(&self, shape_id: &str) -> Result<Shape, Error> {
+        self.limited(
+            key(Endpoint::Shape(shape_id)),
+            self.inner.get_shape(shape_id),
+        )
+        .await
+    }
+
+    async fn get_all_vehicles(&self) -> Result<Vec<Vehicle>, Error> {
+        self.limited(key(Endpoint::Vehicles), self.inner.get_all_vehicles())
+            .await
+    }
+
+    async fn get_municipalities(&self) -> Result<Vec<Municipality>, Error> {
+        self.limited(
+            key(Endpoint::Municipalities),
+            self.inner.get_municipalities(),
+        )
+        .await
+    }
+
+    async fn get_districts(&self) -> Result<Vec<District>, Error> {
+        self.limited(key(Endpoint::Districts), self.inner.get_districts())
+            .await
+    }
+
+    async fn get_regions(&self) -> Result<Vec<Region>, Error> {
+        self.limited(key(Endpoint::Regions), self.inner.get_regions())
+            .await
+    }
+
+    async fn get_alerts(&self) -> Result<Vec<Alert>, Error> {
+        self.limited(key(Endpoint::Alerts), self.inner.get_alerts())
+            .await
+    }
+}
+
+fn key(endpoint: Endpoint<'_>) -> String {
+    endpoint.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn bucket_allows_bursts_then_refills() {
+        let bucket = TokenBucket::new(2, 4.0);
+        let start = Instant::now();
+        assert!(bucket.try_take(start).is_ok());
+        assert!(bucket.try_take(start).is_ok());
+        assert_eq!(bucket.try_take(start), Err(Duration::from_millis(250)));
+        assert!(bucket.try_take(start + Duration::from_millis(250)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_for_a_key_share_one_call() {
+        let coalescer = Coalescer::new();
+        let calls = AtomicUsize::new(0);
+        let request = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(vec![1, 2, 3])
+        };
+
+        let (a, b) = tokio::join!(
+            coalescer.run("020387".into(), request()),
+            coalescer.run("020387".into(), request()),
+        );
+        assert_eq!(a, Ok(vec![1, 2, 3]));
+        assert_eq!(a, b);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let _ = coalescer.run("020387".into(), request()).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn finished_leaders_leave_their_successor_registered() {
+        let coalescer = Coalescer::new();
+        let key = ("020387".to_owned(), TypeId::of::<u8>());
+        let register = || {
+            let sender: Box<dyn Any + Send> = Box::new(Sender::<u8>::new(1));
+            coalescer
+                .in_flight
+                .lock()
+                .unwrap()
+                .insert(key.clone(), sender);
+        };
+
+        register();
+        let leader = Leader {
+            coalescer: &coalescer,
+            key: key.clone(),
+            finished: false,
+        };
+        assert!(leader.finish().is_some());
+
+        // A new leader registered right after; dropping a finished leader
+        // must not remove it, dropping a cancelled one must.
+        register();
+        drop(Leader {
+            coalescer: &coalescer,
+            key: key.clone(),
+            finished: true,
+        });
+        assert!(coalescer.in_flight.lock().unwrap().contains_key(&key));
+        drop(Leader {
+            coalescer: &coalescer,
+            key: key.clone(),
+            finished: false,
+        });
+        assert!(coalescer.in_flight.lock().unwrap().is_empty());
+    }
+}
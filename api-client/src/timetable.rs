@@ -0,0 +1,279 @@
+//! Scheduled departures without the network.
+//!
+//! A [`Timetable`] indexes the trips of a set of [`Pattern`]s by stop, from
+//! the API or from a GTFS feed, and answers which trips leave a stop next.
+//! The arrivals it returns carry schedule information only.
+
+use crate::types::{Arrival, Pattern, ServiceDate, ServiceTime};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// How many service days ahead to look for departures.
+const HORIZON_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Default)]
+pub struct Timetable {
+    trips: Vec<Trip>,
+    /// Sorted service dates, shared by the trips of a service.
+    services: Vec<Vec<ServiceDate>>,
+    /// Departures by stop id, ordered by time since the start of the
+    /// service day.
+    stops: BTreeMap<String, Vec<Departure>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Trip {
+    pub(crate) trip_id: Option<String>,
+    pub(crate) pattern_id: String,
+    pub(crate) route_id: String,
+    pub(crate) line_id: i16,
+    pub(crate) headsign: String,
+    /// Index into `Timetable::services`.
+    pub(crate) service: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Departure {
+    time: ServiceTime,
+    trip: usize,
+    stop_sequence: u16,
+    arrival_time: String,
+}
+
+impl Timetable {
+    pub fn new(patterns: &[Pattern]) -> Self {
+        let mut timetable = Self::default();
+        let mut services: BTreeMap<&[String], usize> = BTreeMap::new();
+        for pattern in patterns {
+            for pattern_trip in &pattern.trips {
+                let service = *services
+                    .entry(pattern_trip.dates.as_slice())
+                    .or_insert_with(|| {
+                        timetable.add_service(
+                            pattern_trip
+                                .dates
+                                .iter()
+                                .filter_map(|d| ServiceDate::parse(d))
+                                .collect(),
+                        )
+                    });
+
+                timetable.add_trip(
+                    Trip {
+                        trip_id: pattern_trip.trip_ids.first().cloned(),
+                        pattern_id: pattern.id.clone(),
+                        route_id: pattern.route_id.clone(),
+                        line_id: pattern.line_id.parse().unwrap_or_default(),
+                        headsign: pattern.headsign.clone(),
+                        service,
+                    },
+                    pattern_trip
+                        .schedule
+                        .iter()
+                        .map(|s| (s.stop_id.as_str(), s.stop_sequence, s.arrival_time.as_str())),
+                );
+            }
+        }
+        timetable.sort();
+        timetable
+    }
+
+    /// Registers the dates of a service and returns its index for
+    /// [`Trip::service`].
+    pub(crate) fn add_service(&mut self, mut dates: Vec<ServiceDate>) -> usize {
+        dates.sort_unstable();
+        dates.dedup();
+        self.services.push(dates);
+        self.services.len() - 1
+    }
+
+    /// Adds a trip and its `(stop_id, stop_sequence, arrival_time)` stops;
+    /// call [`Timetable::sort`] once every trip is in.
+    pub(crate) fn add_trip<'a>(
+        &mut self,
+        trip: Trip,
+        schedule: impl IntoIterator<Item = (&'a str, u16, &'a str)>,
+    ) {
+        let index = self.trips.len();
+        self.trips.push(trip);
+        for (stop_id, stop_sequence, arrival_time) in schedule {
+            let Some(time) = ServiceTime::parse(arrival_time) else {
+                continue;
+            };
+            let departure = Departure {
+                time,
+                trip: index,
+                stop_sequence,
+                arrival_time: arrival_time.to_string(),
+            };
+            match self.stops.get_mut(stop_id) {
+                Some(departures) => departures.push(departure),
+                None => {
+                    self.stops
+                        .insert(stop_id.to_string(), alloc::vec![departure]);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn sort(&mut self) {
+        for departures in self.stops.values_mut() {
+            departures.sort_by_key(|d| d.time);
+        }
+    }
+
+    /// When `trip_id` is scheduled at `stop_id` on the service day `date`.
+    pub fn scheduled_unix(&self, trip_id: &str, stop_id: &str, date: ServiceDate) -> Option<i64> {
+        self.stops
+            .get(stop_id)?
+            .iter()
+            .find(|d| self.trips[d.trip].trip_id.as_deref() == Some(trip_id))
+            .map(|d| d.time.to_unix(date))
+    }
+
+    /// The next `limit` scheduled departures at `stop_id` strictly after
+    /// `after_unix`, in order.
+    ///
+    /// Trips of earlier service days that run past midnight are included.
+    pub fn next_departures(&self, stop_id: &str, after_unix: i64, limit: usize) -> Vec<Arrival> {
+        let Some(departures) = self.stops.get(stop_id) else {
+            return Vec::new();
+        };
+        if limit == 0 {
+            return Vec::new();
+        }
+
+        // Trips of earlier service days may still be running.
+        let lookback = departures
+            .last()
+            .map_or(0, |d| i64::from(d.time.secs()) / DAY);
+        let today = ServiceDate::of_unix(after_unix).epoch_days();
+        let mut found: Vec<(i64, &Departure)> = Vec::new();
+
+        for day in today - lookback..=today + HORIZON_DAYS {
+            let date = ServiceDate::from_epoch_days(day);
+            if found.len() >= limit {
+                found.sort_by_key(|(time, _)| *time);
+                if date.start_unix() > found[limit - 1].0 {
+                    break;
+                }
+            }
+
+            let first = departures.partition_point(|d| d.time.to_unix(date) <= after_unix);
+            found.extend(
+                departures[first..]
+                    .iter()
+                    .filter(|d| {
+                        self.services[self.trips[d.trip].service]
+                            .binary_search(&date)
+                            .is_ok()
+                    })
+                    .map(|d| (d.time.to_unix(date), d)),
+            );
+        }
+
+        found.sort_by_key(|(time, _)| *time);
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(time, d)| {
+                let trip = &self.trips[d.trip];
+                Arrival {
+                    scheduled_arrival_unix: Some(time),
+                    scheduled_arrival: Some(d.arrival_time.clone()),
+                    line_id: trip.line_id,
+                    headsign: trip.headsign.clone(),
+                    trip_id: trip.trip_id.clone(),
+                    pattern_id: Some(trip.pattern_id.clone()),
+                    route_id: Some(trip.route_id.clone()),
+                    stop_id: Some(stop_id.to_string()),
+                    stop_sequence: Some(d.stop_sequence),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PatternTrip, ScheduledStop};
+    use alloc::vec;
+
+    /// 2026-01-05 and 2026-07-06, both Mondays, at 00:00 UTC.
+    const WINTER: i64 = 1_767_571_200;
+    const SUMMER: i64 = 1_783_296_000;
+
+    fn trip(id: &str, dates: &[&str], time: &str) -> PatternTrip {
+        PatternTrip {
+            service_id: "WKD".into(),
+            trip_ids: vec![id.into()],
+            dates: dates.iter().map(|d| d.to_string()).collect(),
+            schedule: vec![ScheduledStop {
+                stop_id: "020387".into(),
+                stop_sequence: 1,
+                arrival_time: time.into(),
+                travel_time: None,
+            }],
+        }
+    }
+
+    fn timetable() -> Timetable {
+        Timetable::new(&[Pattern {
+            id: "1001_0_1".into(),
+            line_id: "1001".into(),
+            route_id: "1001_0".into(),
+            headsign: "Almada".into(),
+            trips: vec![
+                trip("t1", &["20260105", "20260706"], "08:00:00"),
+                trip("t2", &["20260105"], "23:30:00"),
+                trip("t3", &["20260105"], "24:10:00"),
+                trip("t4", &["20260106"], "06:00:00"),
+            ],
+            ..Default::default()
+        }])
+    }
+
+    fn trips(arrivals: &[Arrival]) -> Vec<(&str, i64)> {
+        arrivals
+            .iter()
+            .map(|a| {
+                (
+                    a.trip_id.as_deref().unwrap(),
+                    a.scheduled_arrival_unix.unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn runs_past_midnight_into_the_next_service_day() {
+        let timetable = timetable();
+        let late = timetable.next_departures("020387", WINTER + 23 * 3600, 3);
+        assert_eq!(
+            trips(&late),
+            [
+                ("t2", WINTER + 23 * 3600 + 1800),
+                ("t3", WINTER + DAY + 600),
+                ("t4", WINTER + DAY + 6 * 3600),
+            ]
+        );
+        assert!(late.iter().all(|a| a.estimated_arrival_unix.is_none()));
+
+        // From the next calendar day the trip of the day before still shows.
+        let after_midnight = timetable.next_departures("020387", WINTER + DAY + 60, 1);
+        assert_eq!(trips(&after_midnight), [("t3", WINTER + DAY + 600)]);
+    }
+
+    #[test]
+    fn honours_calendars_and_summer_time() {
+        let timetable = timetable();
+        let summer = timetable.next_departures("020387", SUMMER, 5);
+        assert_eq!(trips(&summer), [("t1", SUMMER + 7 * 3600)]);
+        assert!(timetable.next_departures("140012", SUMMER, 5).is_empty());
+    }
+}
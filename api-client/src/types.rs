@@ -1,6 +1,11 @@
+use crate::Error;
 use alloc::string::String;
-use serde::{Deserialize, Deserializer, Serialize};
-use serde_json::Value;
+use alloc::vec::Vec;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+mod service_time;
+
+pub use service_time::{ServiceDate, ServiceTime};
 
 // https://transform.tools/json-to-rust-serde
 #[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -14,11 +19,84 @@ pub struct Arrival {
 
     pub headsign: String,
     pub scheduled_arrival: Option<String>,
+
+    #[serde(default)]
+    pub trip_id: Option<String>,
+    #[serde(default)]
+    pub pattern_id: Option<String>,
+    #[serde(default)]
+    pub route_id: Option<String>,
+    #[serde(default)]
+    pub vehicle_id: Option<String>,
+    /// Only present in `by_pattern` and `by_trip` responses.
+    #[serde(default)]
+    pub stop_id: Option<String>,
+    #[serde(default)]
+    pub stop_sequence: Option<u16>,
+    /// The trip was cancelled or will skip this stop.
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+/// How long a vehicle is taken to stand at a stop after arriving.
+const DWELL_SECS: i64 = 60;
+
+/// What is known about an arrival at a given moment, see [`Arrival::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrivalStatus {
+    /// Only the timetable is known.
+    Scheduled,
+    /// Predicted from the vehicle's position; `delay_secs` is negative when
+    /// the vehicle runs early.
+    Realtime {
+        delay_secs: i64,
+    },
+    /// The vehicle is at the stop.
+    Arrived,
+    /// The vehicle has left the stop.
+    Departed,
+    Cancelled,
 }
 
 impl Arrival {
+    /// Whether the best known arrival time is still ahead, so a late bus
+    /// counts until its estimate passes.
     pub fn is_future(&self, now_unix: i64) -> bool {
-        self.scheduled_arrival_unix.unwrap_or_default() > now_unix
+        best_arrival_unix(self).unwrap_or_default() > now_unix
+    }
+
+    pub fn status(&self, now_unix: i64) -> ArrivalStatus {
+        if self.cancelled {
+            return ArrivalStatus::Cancelled;
+        }
+        if let Some(observed) = self.observed_arrival_unix {
+            return if now_unix - observed < DWELL_SECS {
+                ArrivalStatus::Arrived
+            } else {
+                ArrivalStatus::Departed
+            };
+        }
+        match (self.estimated_arrival_unix, self.scheduled_arrival_unix) {
+            (Some(estimated), _) if now_unix - estimated >= DWELL_SECS => ArrivalStatus::Departed,
+            (Some(estimated), scheduled) => ArrivalStatus::Realtime {
+                delay_secs: scheduled.map_or(0, |scheduled| estimated - scheduled),
+            },
+            (None, Some(scheduled)) if now_unix - scheduled >= DWELL_SECS => {
+                ArrivalStatus::Departed
+            }
+            (None, _) => ArrivalStatus::Scheduled,
+        }
+    }
+
+    /// Whole minutes until the best known arrival time, negative once it
+    /// has passed.
+    pub fn minutes_until(&self, now_unix: i64) -> Option<i64> {
+        best_arrival_unix(self).map(|time| (time - now_unix).div_euclid(60))
+    }
+
+    /// `scheduled_arrival` parsed, if present and well formed.
+    pub fn scheduled_time(&self) -> Option<ServiceTime> {
+        ServiceTime::parse(self.scheduled_arrival.as_deref()?)
     }
 }
 
@@ -26,8 +104,8 @@ impl Arrival {
 #[serde(rename_all = "camelCase")]
 pub struct Stop {
     #[serde(rename = "district_id")]
-    pub district_id: Value,
-    pub facilities: Vec<Value>,
+    pub district_id: Option<String>,
+    pub facilities: Vec<Facility>,
     pub id: String,
     pub lat: f64,
     #[serde(rename = "line_ids")]
@@ -36,21 +114,521 @@ pub struct Stop {
     #[serde(rename = "long_name")]
     pub long_name: String,
     #[serde(rename = "municipality_id")]
-    pub municipality_id: Value,
+    pub municipality_id: Option<String>,
     #[serde(rename = "pattern_ids")]
     pub pattern_ids: Vec<String>,
     #[serde(rename = "region_id")]
-    pub region_id: Value,
+    pub region_id: Option<String>,
     #[serde(rename = "route_ids")]
     pub route_ids: Vec<String>,
     #[serde(rename = "short_name")]
-    pub short_name: Value,
+    pub short_name: Option<String>,
     #[serde(rename = "tts_name")]
     pub tts_name: String,
     #[serde(rename = "wheelchair_boarding")]
     pub wheelchair_boarding: bool,
 }
 
+/// Points of interest and interchanges near a stop or along a line.
+///
+/// Values the client does not know about yet are kept in `Unknown`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Facility {
+    Airport,
+    BikeParking,
+    BikeSharing,
+    Boat,
+    CarParking,
+    FireStation,
+    HealthClinic,
+    HistoricBuilding,
+    Hospital,
+    LightRail,
+    Market,
+    PoliceStation,
+    School,
+    Shopping,
+    Subway,
+    Train,
+    TransitOffice,
+    University,
+    Unknown(String),
+}
+
+impl Facility {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Facility::Airport => "airport",
+            Facility::BikeParking => "bike_parking",
+            Facility::BikeSharing => "bike_sharing",
+            Facility::Boat => "boat",
+            Facility::CarParking => "car_parking",
+            Facility::FireStation => "fire_station",
+            Facility::HealthClinic => "health_clinic",
+            Facility::HistoricBuilding => "historic_building",
+            Facility::Hospital => "hospital",
+            Facility::LightRail => "light_rail",
+            Facility::Market => "market",
+            Facility::PoliceStation => "police_station",
+            Facility::School => "school",
+            Facility::Shopping => "shopping",
+            Facility::Subway => "subway",
+            Facility::Train => "train",
+            Facility::TransitOffice => "transit_office",
+            Facility::University => "university",
+            Facility::Unknown(s) => s,
+        }
+    }
+}
+
+impl From<String> for Facility {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "airport" => Facility::Airport,
+            "bike_parking" => Facility::BikeParking,
+            "bike_sharing" => Facility::BikeSharing,
+            "boat" => Facility::Boat,
+            "car_parking" => Facility::CarParking,
+            "fire_station" => Facility::FireStation,
+            "health_clinic" => Facility::HealthClinic,
+            "historic_building" => Facility::HistoricBuilding,
+            "hospital" => Facility::Hospital,
+            "light_rail" => Facility::LightRail,
+            "market" => Facility::Market,
+            "police_station" => Facility::PoliceStation,
+            "school" => Facility::School,
+            "shopping" => Facility::Shopping,
+            "subway" => Facility::Subway,
+            "train" => Facility::Train,
+            "transit_office" => Facility::TransitOffice,
+            "university" => Facility::University,
+            _ => Facility::Unknown(s),
+        }
+    }
+}
+
+impl Serialize for Facility {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Facility {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Facility::from)
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Municipality {
+    pub id: String,
+    pub name: String,
+    pub prefix: Option<String>,
+    pub district_id: Option<String>,
+    pub region_id: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct District {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Line {
+    pub id: String,
+    pub short_name: String,
+    pub long_name: String,
+    pub tts_name: String,
+    /// Background colour of the line badge as `#RRGGBB`.
+    pub color: String,
+    /// Foreground colour of the line badge as `#RRGGBB`.
+    pub text_color: String,
+    pub facilities: Vec<Facility>,
+    pub pattern_ids: Vec<String>,
+    pub route_ids: Vec<String>,
+    #[serde(default)]
+    pub district_ids: Vec<String>,
+    #[serde(default)]
+    pub locality_ids: Vec<String>,
+    #[serde(default)]
+    pub municipality_ids: Vec<String>,
+    #[serde(default)]
+    pub region_ids: Vec<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub id: String,
+    pub line_id: String,
+    pub route_id: String,
+    pub shape_id: String,
+    pub short_name: String,
+    pub headsign: String,
+    pub direction_id: u8,
+    pub color: String,
+    pub text_color: String,
+    pub facilities: Vec<Facility>,
+    /// Stops served by this pattern, ordered by `stop_sequence`.
+    #[serde(deserialize_with = "de_path_in_order")]
+    pub path: Vec<PathStop>,
+    #[serde(default)]
+    pub trips: Vec<PatternTrip>,
+    #[serde(default)]
+    pub locality_ids: Vec<String>,
+    #[serde(default)]
+    pub municipality_ids: Vec<String>,
+}
+
+impl Pattern {
+    /// Stops still to be served after the stop with the given `stop_sequence`.
+    pub fn remaining_stops(&self, stop_sequence: u16) -> impl Iterator<Item = &PathStop> {
+        self.path
+            .iter()
+            .filter(move |p| p.stop_sequence > stop_sequence)
+    }
+
+    pub fn position_of(&self, stop_id: &str) -> Option<&PathStop> {
+        self.path.iter().find(|p| p.stop_id == stop_id)
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathStop {
+    pub stop_id: String,
+    pub stop_sequence: u16,
+    pub allow_pickup: bool,
+    pub allow_drop_off: bool,
+    /// Distance from the previous stop, in metres.
+    #[serde(default)]
+    pub distance_delta: f64,
+    /// Distance from the start of the pattern, in metres.
+    #[serde(default)]
+    pub shape_dist_traveled: f64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternTrip {
+    pub service_id: String,
+    pub trip_ids: Vec<String>,
+    /// Service dates as `YYYYMMDD`.
+    #[serde(default)]
+    pub dates: Vec<String>,
+    pub schedule: Vec<ScheduledStop>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledStop {
+    pub stop_id: String,
+    pub stop_sequence: u16,
+    /// GTFS `HH:MM:SS`, may exceed `24:00:00` for trips past midnight.
+    pub arrival_time: String,
+    #[serde(default)]
+    pub travel_time: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shape {
+    pub id: String,
+    /// Polyline vertices, ordered by `shape_pt_sequence`.
+    #[serde(deserialize_with = "de_points_in_order")]
+    pub points: Vec<ShapePoint>,
+    /// Total length of the shape, in metres.
+    #[serde(default)]
+    pub extension: f64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShapePoint {
+    pub shape_pt_lat: f64,
+    pub shape_pt_lon: f64,
+    pub shape_pt_sequence: u32,
+    /// Distance from the first point of the shape, in metres.
+    pub shape_dist_traveled: f64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vehicle {
+    pub id: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Heading in degrees clockwise from north.
+    #[serde(default)]
+    pub bearing: f64,
+    /// Speed in metres per second.
+    #[serde(default)]
+    pub speed: f64,
+    pub line_id: Option<String>,
+    pub route_id: Option<String>,
+    pub pattern_id: Option<String>,
+    pub trip_id: Option<String>,
+    /// The stop the vehicle is at or heading to, see `current_status`.
+    pub stop_id: Option<String>,
+    pub current_status: Option<VehicleStatus>,
+    /// Unix time of the position report.
+    pub timestamp: i64,
+    #[serde(default)]
+    pub occupancy_status: Option<Occupancy>,
+}
+
+impl Vehicle {
+    /// Number of stops the vehicle still has to reach along `pattern`, up
+    /// to and including `stop_id`: `0` while it stands at `stop_id`, `1`
+    /// when `stop_id` is its next stop.
+    ///
+    /// Stops are counted by their position in `pattern.path`, as GTFS
+    /// `stop_sequence` values may skip numbers. Returns `None` if either
+    /// stop is not part of the pattern or the vehicle has already passed
+    /// `stop_id`.
+    pub fn stops_away(&self, pattern: &Pattern, stop_id: &str) -> Option<u16> {
+        let vehicle_stop = self.stop_id.as_deref()?;
+        let current = pattern
+            .path
+            .iter()
+            .position(|p| p.stop_id == vehicle_stop)?;
+        let away = pattern.path[current..]
+            .iter()
+            .position(|p| p.stop_id == stop_id)?;
+        let away = u16::try_from(away).ok()?;
+
+        match self.current_status {
+            Some(VehicleStatus::StoppedAt) => Some(away),
+            // An approaching vehicle has yet to reach its current stop, and
+            // GTFS-Realtime means approaching when the status is missing.
+            Some(VehicleStatus::InTransitTo | VehicleStatus::IncomingAt) | None => {
+                away.checked_add(1)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum VehicleStatus {
+    IncomingAt,
+    StoppedAt,
+    InTransitTo,
+}
+
+/// GTFS-Realtime `OccupancyStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Occupancy {
+    Empty,
+    ManySeatsAvailable,
+    FewSeatsAvailable,
+    StandingRoomOnly,
+    CrushedStandingRoomOnly,
+    Full,
+    NotAcceptingPassengers,
+    #[serde(other)]
+    Unknown,
+}
+
+/// The `/alerts` response, a GTFS-Realtime `FeedMessage` rendered as JSON.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertFeed {
+    #[serde(default)]
+    pub entity: Vec<AlertEntity>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertEntity {
+    pub id: String,
+    pub alert: Alert,
+}
+
+impl AlertFeed {
+    pub fn into_alerts(self) -> Vec<Alert> {
+        self.entity
+            .into_iter()
+            .map(|e| Alert {
+                id: e.id,
+                ..e.alert
+            })
+            .collect()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    /// Taken from the enclosing feed entity.
+    #[serde(default)]
+    pub id: String,
+    #[serde(default, alias = "activePeriod", alias = "active_period")]
+    pub active_periods: Vec<ActivePeriod>,
+    #[serde(default)]
+    pub cause: AlertCause,
+    #[serde(default)]
+    pub effect: AlertEffect,
+    #[serde(default, alias = "headerText")]
+    pub header_text: TranslatedString,
+    #[serde(default, alias = "descriptionText")]
+    pub description_text: TranslatedString,
+    #[serde(default)]
+    pub url: Option<TranslatedString>,
+    #[serde(default, alias = "informedEntity", alias = "informed_entity")]
+    pub informed_entities: Vec<InformedEntity>,
+}
+
+impl Alert {
+    /// An alert without active periods is active for as long as it is published.
+    pub fn is_active(&self, now_unix: i64) -> bool {
+        self.active_periods.is_empty() || self.active_periods.iter().any(|p| p.contains(now_unix))
+    }
+
+    pub fn affects_stop(&self, stop: &Stop) -> bool {
+        self.informed_entities.iter().any(|e| match e {
+            InformedEntity {
+                stop_id: Some(stop_id),
+                ..
+            } => *stop_id == stop.id,
+            InformedEntity {
+                route_id: Some(route_id),
+                ..
+            } => stop.route_ids.contains(route_id),
+            _ => false,
+        })
+    }
+
+    /// Entities naming a route of the line count as well, also when they
+    /// limit it to a stop.
+    pub fn affects_line(&self, line_id: &str) -> bool {
+        self.informed_entities.iter().any(|e| {
+            e.route_id
+                .as_deref()
+                .is_some_and(|route_id| line_id_of_route(route_id) == line_id)
+        })
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivePeriod {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+impl ActivePeriod {
+    pub fn contains(&self, now_unix: i64) -> bool {
+        self.start.is_none_or(|start| start <= now_unix)
+            && self.end.is_none_or(|end| now_unix < end)
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranslatedString {
+    #[serde(default)]
+    pub translation: Vec<Translation>,
+}
+
+impl TranslatedString {
+    /// Text in `language`, falling back to the first translation.
+    pub fn get(&self, language: &str) -> Option<&str> {
+        self.translation
+            .iter()
+            .find(|t| t.language.as_deref() == Some(language))
+            .or(self.translation.first())
+            .map(|t| t.text.as_str())
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Translation {
+    pub text: String,
+    pub language: Option<String>,
+}
+
+/// A GTFS-Realtime `EntitySelector`; an alert applies to the stops and
+/// routes named by its informed entities.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InformedEntity {
+    #[serde(default, alias = "agencyId")]
+    pub agency_id: Option<String>,
+    #[serde(default, alias = "routeId")]
+    pub route_id: Option<String>,
+    #[serde(default, alias = "stopId")]
+    pub stop_id: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AlertCause {
+    OtherCause,
+    TechnicalProblem,
+    Strike,
+    Demonstration,
+    Accident,
+    Holiday,
+    Weather,
+    Maintenance,
+    Construction,
+    PoliceActivity,
+    MedicalEmergency,
+    #[default]
+    #[serde(other)]
+    UnknownCause,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AlertEffect {
+    NoService,
+    ReducedService,
+    SignificantDelays,
+    Detour,
+    AdditionalService,
+    ModifiedService,
+    OtherEffect,
+    StopMoved,
+    NoEffect,
+    AccessibilityIssue,
+    #[default]
+    #[serde(other)]
+    UnknownEffect,
+}
+
+/// The line of a Carris route: route ids are the line id followed by
+/// `_<n>`, and a bare line id names the line itself.
+pub fn line_id_of_route(route_id: &str) -> &str {
+    route_id
+        .split_once('_')
+        .map_or(route_id, |(line_id, _)| line_id)
+}
+
+pub fn alerts_for_stop<'a>(alerts: &'a [Alert], stop: &Stop) -> Vec<&'a Alert> {
+    alerts.iter().filter(|a| a.affects_stop(stop)).collect()
+}
+
+pub fn alerts_for_line<'a>(alerts: &'a [Alert], line_id: &str) -> Vec<&'a Alert> {
+    alerts.iter().filter(|a| a.affects_line(line_id)).collect()
+}
+
+/// The API does not promise any order for `Pattern::path`.
+fn de_path_in_order<'de, D>(deserializer: D) -> Result<Vec<PathStop>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut path = Vec::<PathStop>::deserialize(deserializer)?;
+    path.sort_by_key(|p| p.stop_sequence);
+    Ok(path)
+}
+
+/// Nor for `Shape::points`, which a polyline needs in order.
+fn de_points_in_order<'de, D>(deserializer: D) -> Result<Vec<ShapePoint>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut points = Vec::<ShapePoint>::deserialize(deserializer)?;
+    points.sort_by_key(|p| p.shape_pt_sequence);
+    Ok(points)
+}
+
 fn de_i16_from_string<'de, D>(deserializer: D) -> Result<i16, D::Error>
 where
     D: Deserializer<'de>,
@@ -59,18 +637,77 @@ where
     s.parse::<i16>().map_err(serde::de::Error::custom)
 }
 
-pub trait CarrisAPI {
-    type Error;
+/// Backends and decorators that can be built around an injected HTTP
+/// transport.
+pub trait FromTransport {
+    /// HTTP stack the client sends its requests through.
+    type Transport;
 
-    fn new() -> Self;
-    fn new_with_base_url(base_url: &str) -> Self;
+    fn from_transport(transport: Self::Transport) -> Self;
+    fn from_transport_with_base_url(transport: Self::Transport, base_url: &str) -> Self;
+}
 
+/// Common interface of the std and embedded clients.
+///
+/// Methods take `&self` so one client can be shared between tasks; backends
+/// that need exclusive access to their transport serialise requests
+/// internally.
+pub trait CarrisAPI {
     fn arrivals_by_stop<'a>(
         &'a self,
         stop: &'a str,
-    ) -> impl Future<Output = Result<Vec<Arrival>, Self::Error>> + 'a;
+    ) -> impl Future<Output = Result<Vec<Arrival>, Error>> + 'a;
+
+    fn arrivals_by_pattern<'a>(
+        &'a self,
+        pattern_id: &'a str,
+    ) -> impl Future<Output = Result<Vec<Arrival>, Error>> + 'a;
+
+    fn arrivals_by_trip<'a>(
+        &'a self,
+        trip_id: &'a str,
+    ) -> impl Future<Output = Result<Vec<Arrival>, Error>> + 'a;
+
+    fn get_all_stops<'a>(&'a self) -> impl Future<Output = Result<Vec<Stop>, Error>> + 'a;
 
-    fn get_all_stops<'a>(&'a self) -> impl Future<Output = Result<Vec<Stop>, Self::Error>> + 'a;
+    fn get_all_lines<'a>(&'a self) -> impl Future<Output = Result<Vec<Line>, Error>> + 'a;
+
+    fn get_line<'a>(&'a self, line_id: &'a str) -> impl Future<Output = Result<Line, Error>> + 'a;
+
+    fn get_pattern<'a>(
+        &'a self,
+        pattern_id: &'a str,
+    ) -> impl Future<Output = Result<Pattern, Error>> + 'a;
+
+    fn get_shape<'a>(
+        &'a self,
+        shape_id: &'a str,
+    ) -> impl Future<Output = Result<Shape, Error>> + 'a;
+
+    fn get_all_vehicles<'a>(&'a self) -> impl Future<Output = Result<Vec<Vehicle>, Error>> + 'a;
+
+    /// The API has no endpoint per line, so this filters
+    /// [`get_all_vehicles`](Self::get_all_vehicles).
+    fn vehicles_by_line<'a>(
+        &'a self,
+        line_id: &'a str,
+    ) -> impl Future<Output = Result<Vec<Vehicle>, Error>> + 'a {
+        async move {
+            let mut vehicles = self.get_all_vehicles().await?;
+            vehicles.retain(|v| v.line_id.as_deref() == Some(line_id));
+            Ok(vehicles)
+        }
+    }
+
+    fn get_municipalities<'a>(
+        &'a self,
+    ) -> impl Future<Output = Result<Vec<Municipality>, Error>> + 'a;
+
+    fn get_districts<'a>(&'a self) -> impl Future<Output = Result<Vec<District>, Error>> + 'a;
+
+    fn get_regions<'a>(&'a self) -> impl Future<Output = Result<Vec<Region>, Error>> + 'a;
+
+    fn get_alerts<'a>(&'a self) -> impl Future<Output = Result<Vec<Alert>, Error>> + 'a;
 }
 
 pub fn best_arrival_unix(a: &Arrival) -> Option<i64> {
@@ -78,3 +715,262 @@ pub fn best_arrival_unix(a: &Arrival) -> Option<i64> {
         .or(a.observed_arrival_unix)
         .or(a.scheduled_arrival_unix)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_767_600_000;
+
+    fn arrival(scheduled: i64, estimated: Option<i64>) -> Arrival {
+        Arrival {
+            scheduled_arrival_unix: Some(scheduled),
+            estimated_arrival_unix: estimated,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn classifies_arrivals() {
+        let on_time = arrival(NOW + 300, Some(NOW + 300));
+        assert_eq!(
+            on_time.status(NOW),
+            ArrivalStatus::Realtime { delay_secs: 0 }
+        );
+        assert_eq!(on_time.minutes_until(NOW), Some(5));
+
+        // Scheduled two minutes ago, due in three.
+        let late = arrival(NOW - 120, Some(NOW + 180));
+        assert_eq!(
+            late.status(NOW),
+            ArrivalStatus::Realtime { delay_secs: 300 }
+        );
+        assert_eq!(late.minutes_until(NOW), Some(3));
+        assert!(late.is_future(NOW));
+
+        let cancelled = Arrival {
+            cancelled: true,
+            ..on_time.clone()
+        };
+        assert_eq!(cancelled.status(NOW), ArrivalStatus::Cancelled);
+
+        let scheduled = arrival(NOW + 59, None);
+        assert_eq!(scheduled.status(NOW), ArrivalStatus::Scheduled);
+        assert_eq!(scheduled.minutes_until(NOW), Some(0));
+        assert_eq!(
+            arrival(NOW - 30, None).status(NOW),
+            ArrivalStatus::Scheduled
+        );
+        assert_eq!(
+            arrival(NOW - 600, None).status(NOW),
+            ArrivalStatus::Departed
+        );
+        assert_eq!(arrival(NOW - 600, None).minutes_until(NOW), Some(-10));
+        assert!(!arrival(NOW - 600, None).is_future(NOW));
+
+        let observed = Arrival {
+            observed_arrival_unix: Some(NOW - 30),
+            ..on_time
+        };
+        assert_eq!(observed.status(NOW), ArrivalStatus::Arrived);
+        assert_eq!(observed.status(NOW + 60), ArrivalStatus::Departed);
+        assert_eq!(Arrival::default().minutes_until(NOW), None);
+    }
+
+    #[test]
+    fn counts_stops_by_path_position() {
+        // Sequences skip numbers, as GTFS allows.
+        let pattern = Pattern {
+            path: ["a", "b", "c", "d"]
+                .iter()
+                .zip([1, 5, 10, 20])
+                .map(|(id, stop_sequence)| PathStop {
+                    stop_id: (*id).into(),
+                    stop_sequence,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let vehicle = |stop: &str, status| Vehicle {
+            stop_id: Some(stop.into()),
+            current_status: status,
+            ..Default::default()
+        };
+
+        let stopped = vehicle("b", Some(VehicleStatus::StoppedAt));
+        assert_eq!(stopped.stops_away(&pattern, "b"), Some(0));
+        assert_eq!(stopped.stops_away(&pattern, "d"), Some(2));
+        assert_eq!(stopped.stops_away(&pattern, "a"), None);
+        assert_eq!(stopped.stops_away(&pattern, "x"), None);
+
+        let approaching = vehicle("b", Some(VehicleStatus::InTransitTo));
+        assert_eq!(approaching.stops_away(&pattern, "b"), Some(1));
+        assert_eq!(approaching.stops_away(&pattern, "c"), Some(2));
+        assert_eq!(vehicle("c", None).stops_away(&pattern, "d"), Some(2));
+    }
+
+    fn alert(entities: &[(Option<&str>, Option<&str>)]) -> Alert {
+        Alert {
+            informed_entities: entities
+                .iter()
+                .map(|(route_id, stop_id)| InformedEntity {
+                    agency_id: None,
+                    route_id: route_id.map(Into::into),
+                    stop_id: stop_id.map(Into::into),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matches_alerts_to_stops() {
+        let stop = Stop {
+            id: "020387".into(),
+            route_ids: alloc::vec!["1001_0".into()],
+            ..Default::default()
+        };
+
+        assert!(alert(&[(None, Some("020387"))]).affects_stop(&stop));
+        assert!(alert(&[(Some("1001_0"), None)]).affects_stop(&stop));
+        assert!(alert(&[(Some("1001_0"), Some("020387"))]).affects_stop(&stop));
+        // A route limited to another stop leaves this one alone.
+        assert!(!alert(&[(Some("1001_0"), Some("140012"))]).affects_stop(&stop));
+        assert!(!alert(&[(Some("1002_0"), None)]).affects_stop(&stop));
+        assert!(!alert(&[]).affects_stop(&stop));
+    }
+
+    #[test]
+    fn matches_alerts_to_lines() {
+        assert!(alert(&[(Some("1001_0"), None)]).affects_line("1001"));
+        assert!(alert(&[(Some("1001"), None)]).affects_line("1001"));
+        assert!(alert(&[(Some("1001_1"), Some("020387"))]).affects_line("1001"));
+        assert!(!alert(&[(Some("10010_0"), None)]).affects_line("1001"));
+        assert!(!alert(&[(None, Some("020387"))]).affects_line("1001"));
+        assert_eq!(line_id_of_route("1001_0"), "1001");
+        assert_eq!(line_id_of_route("1001"), "1001");
+
+        let alerts = [
+            alert(&[(Some("1001_0"), None)]),
+            alert(&[(Some("1002_0"), None)]),
+        ];
+        assert_eq!(alerts_for_line(&alerts, "1002"), [&alerts[1]]);
+    }
+
+    #[test]
+    fn keeps_unknown_facilities_and_missing_geography() {
+        let json = r#"{"id":"020387","lat":38.7363,"lon":-9.1389,
+            "long_name":"Av. da República (Saldanha)","short_name":null,
+            "tts_name":"Avenida da República, Saldanha","wheelchair_boarding":true,
+            "facilities":["school","tram_stop"],"line_ids":[],"pattern_ids":[],
+            "route_ids":[],"district_id":null,"municipality_id":"1106",
+            "region_id":null}"#;
+        let stop: Stop = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            stop.facilities,
+            [Facility::School, Facility::Unknown("tram_stop".into())]
+        );
+        assert_eq!(stop.district_id, None);
+        assert_eq!(stop.municipality_id.as_deref(), Some("1106"));
+        assert_eq!(stop.region_id, None);
+
+        let value = serde_json::to_value(&stop).unwrap();
+        assert_eq!(
+            value["facilities"],
+            serde_json::json!(["school", "tram_stop"])
+        );
+        assert_eq!(value["district_id"], serde_json::Value::Null);
+        assert_eq!(serde_json::from_value::<Stop>(value).unwrap(), stop);
+
+        let municipality: Municipality = serde_json::from_str(
+            r#"{"id":"1106","name":"Lisboa","prefix":null,"district_id":"11","region_id":null}"#,
+        )
+        .unwrap();
+        assert_eq!(municipality.prefix, None);
+        assert_eq!(municipality.region_id, None);
+    }
+
+    #[test]
+    fn decodes_lines_without_optional_ids() {
+        let json = r##"{"id":"1001","short_name":"1001",
+            "long_name":"Almada (Centro Sul) - Lisboa (Saldanha)",
+            "tts_name":"Almada, Centro Sul - Lisboa, Saldanha",
+            "color":"#C61D23","text_color":"#FFFFFF","facilities":["boat"],
+            "pattern_ids":["1001_0_1"],"route_ids":["1001_0"],
+            "municipality_ids":["1106","1503"]}"##;
+        let line: Line = serde_json::from_str(json).unwrap();
+        assert_eq!(line.color, "#C61D23");
+        assert_eq!(line.text_color, "#FFFFFF");
+        assert_eq!(line.facilities, [Facility::Boat]);
+        assert_eq!(line.municipality_ids, ["1106", "1503"]);
+        assert!(line.district_ids.is_empty());
+        assert!(line.region_ids.is_empty());
+    }
+
+    #[test]
+    fn walks_the_rest_of_a_pattern() {
+        let json = r##"{"id":"1001_0_1","line_id":"1001","route_id":"1001_0",
+            "shape_id":"20041","short_name":"1001","headsign":"Almada (Centro Sul)",
+            "direction_id":0,"color":"#C61D23","text_color":"#FFFFFF","facilities":[],
+            "path":[
+                {"stop_id":"060001","stop_sequence":4,"allow_pickup":true,"allow_drop_off":true,
+                 "distance_delta":2400.0,"shape_dist_traveled":2400.0},
+                {"stop_id":"020387","stop_sequence":1,"allow_pickup":true,"allow_drop_off":false},
+                {"stop_id":"140012","stop_sequence":9,"allow_pickup":false,"allow_drop_off":true,
+                 "distance_delta":4750.5,"shape_dist_traveled":7150.5}]}"##;
+        let pattern: Pattern = serde_json::from_str(json).unwrap();
+        assert!(pattern.trips.is_empty());
+        // Sent out of order, decoded by `stop_sequence`.
+        assert_eq!(pattern.path[0].stop_id, "020387");
+        assert_eq!(pattern.path[0].distance_delta, 0.0);
+
+        let rest: Vec<&str> = pattern
+            .remaining_stops(1)
+            .map(|p| p.stop_id.as_str())
+            .collect();
+        assert_eq!(rest, ["060001", "140012"]);
+        assert_eq!(pattern.remaining_stops(9).count(), 0);
+        assert_eq!(pattern.position_of("060001").unwrap().stop_sequence, 4);
+        assert!(pattern.position_of("999999").is_none());
+    }
+
+    #[test]
+    fn keeps_trip_and_stop_ids_on_arrivals() {
+        let by_trip = r#"[{"line_id":"1001","headsign":"Almada (Centro Sul)",
+            "trip_id":"1001_0_1|1|0800","pattern_id":"1001_0_1","route_id":"1001_0",
+            "vehicle_id":"41|1234","stop_id":"140012","stop_sequence":2,
+            "scheduled_arrival":"08:23:00","scheduled_arrival_unix":1767601380,
+            "estimated_arrival_unix":null,"observed_arrival_unix":null}]"#;
+        let arrivals: Vec<Arrival> = serde_json::from_str(by_trip).unwrap();
+        assert_eq!(arrivals[0].line_id, 1001);
+        assert_eq!(arrivals[0].trip_id.as_deref(), Some("1001_0_1|1|0800"));
+        assert_eq!(arrivals[0].vehicle_id.as_deref(), Some("41|1234"));
+        assert_eq!(arrivals[0].stop_id.as_deref(), Some("140012"));
+        assert_eq!(arrivals[0].stop_sequence, Some(2));
+
+        // `by_stop` responses leave out the stop and may leave out the ids.
+        let by_stop: Arrival = serde_json::from_str(
+            r#"{"line_id":"1001","headsign":"Almada (Centro Sul)",
+                "scheduled_arrival":null,"scheduled_arrival_unix":null,
+                "estimated_arrival_unix":null,"observed_arrival_unix":null}"#,
+        )
+        .unwrap();
+        assert_eq!(by_stop.trip_id, None);
+        assert_eq!(by_stop.stop_id, None);
+        assert_eq!(by_stop.stop_sequence, None);
+        assert!(!by_stop.cancelled);
+    }
+
+    #[test]
+    fn orders_shape_points_by_sequence() {
+        let json = r#"{"id":"20041","extension":7150.5,"points":[
+            {"shape_pt_lat":38.6786,"shape_pt_lon":-9.1633,"shape_pt_sequence":3,"shape_dist_traveled":7150.5},
+            {"shape_pt_lat":38.7363,"shape_pt_lon":-9.1389,"shape_pt_sequence":1,"shape_dist_traveled":0.0},
+            {"shape_pt_lat":38.7072,"shape_pt_lon":-9.1498,"shape_pt_sequence":2,"shape_dist_traveled":3400.0}]}"#;
+        let shape: Shape = serde_json::from_str(json).unwrap();
+        let sequences: Vec<u32> = shape.points.iter().map(|p| p.shape_pt_sequence).collect();
+        assert_eq!(sequences, [1, 2, 3]);
+        assert_eq!(shape.extension, 7150.5);
+    }
+}
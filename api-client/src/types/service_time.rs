@@ -0,0 +1,233 @@
+//! Service days and times of the GTFS schedule, in Europe/Lisbon time.
+//!
+//! GTFS gives times as `HH:MM:SS` since the start of the service day, and
+//! trips running past midnight keep counting, so `25:10:00` is ten past one
+//! the next morning. The service day starts at noon minus twelve hours,
+//! which is an hour off midnight on the days clocks change.
+
+use core::fmt;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// A service day, as GTFS `YYYYMMDD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceDate {
+    /// Days since 1970-01-01.
+    days: i64,
+}
+
+impl ServiceDate {
+    pub fn from_ymd(year: i32, month: u32, day: u32) -> Option<Self> {
+        let valid = (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day);
+        valid.then(|| Self::from_epoch_days(days_from_civil(year, month, day)))
+    }
+
+    /// Parses GTFS `YYYYMMDD`.
+    pub fn parse(date: &str) -> Option<Self> {
+        let date = date.trim();
+        if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Self::from_ymd(
+            date[..4].parse().ok()?,
+            date[4..6].parse().ok()?,
+            date[6..].parse().ok()?,
+        )
+    }
+
+    pub const fn from_epoch_days(days: i64) -> Self {
+        Self { days }
+    }
+
+    /// Days since 1970-01-01.
+    pub const fn epoch_days(self) -> i64 {
+        self.days
+    }
+
+    pub fn ymd(self) -> (i32, u32, u32) {
+        civil_from_days(self.days)
+    }
+
+    /// The Lisbon calendar day `unix` falls on.
+    pub fn of_unix(unix: i64) -> Self {
+        Self::from_epoch_days((unix + lisbon_offset(unix)).div_euclid(DAY))
+    }
+
+    /// Unix time the service day's times count from.
+    pub fn start_unix(self) -> i64 {
+        let noon = self.days * DAY + 12 * 3600;
+        noon - lisbon_offset(noon) - 12 * 3600
+    }
+}
+
+/// `2026-01-05`.
+impl fmt::Display for ServiceDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{year:04}-{month:02}-{day:02}")
+    }
+}
+
+/// Time since the start of a service day, which may exceed 24 hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceTime {
+    secs: u32,
+}
+
+impl ServiceTime {
+    pub const fn from_secs(secs: u32) -> Self {
+        Self { secs }
+    }
+
+    /// `None` for minutes or seconds past 59, or hours that overflow.
+    pub fn from_hms(hours: u32, minutes: u32, seconds: u32) -> Option<Self> {
+        if minutes >= 60 || seconds >= 60 {
+            return None;
+        }
+        let secs = hours
+            .checked_mul(3600)?
+            .checked_add(minutes * 60 + seconds)?;
+        Some(Self::from_secs(secs))
+    }
+
+    /// Parses GTFS `HH:MM:SS`; the hours may be a single digit or exceed 23.
+    pub fn parse(time: &str) -> Option<Self> {
+        let mut parts = time.trim().split(':').map(|p| p.parse::<u32>().ok());
+        let hms = (parts.next()??, parts.next()??, parts.next()??);
+        if parts.next().is_some() {
+            return None;
+        }
+        Self::from_hms(hms.0, hms.1, hms.2)
+    }
+
+    /// The Lisbon wall-clock time of `unix` and the day it falls on.
+    pub fn from_unix(unix: i64) -> (ServiceDate, Self) {
+        let local = unix + lisbon_offset(unix);
+        (
+            ServiceDate::from_epoch_days(local.div_euclid(DAY)),
+            Self::from_secs(local.rem_euclid(DAY) as u32),
+        )
+    }
+
+    pub const fn secs(self) -> u32 {
+        self.secs
+    }
+
+    pub const fn hms(self) -> (u32, u32, u32) {
+        (self.secs / 3600, self.secs / 60 % 60, self.secs % 60)
+    }
+
+    pub fn to_unix(self, date: ServiceDate) -> i64 {
+        date.start_unix() + i64::from(self.secs)
+    }
+}
+
+/// Wall-clock `HH:MM`, so `25:10:00` shows as `01:10`.
+impl fmt::Display for ServiceTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (hours, minutes, _) = self.hms();
+        write!(f, "{:02}:{minutes:02}", hours % 24)
+    }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year as i32, month, day)
+}
+
+fn last_sunday(year: i32, month: u32) -> i64 {
+    let last = days_from_civil(year, month, days_in_month(year, month));
+    // 1970-01-01 was a Thursday.
+    last - (last + 4).rem_euclid(7)
+}
+
+/// UTC offset of Europe/Lisbon in seconds: summer time runs from 01:00 UTC
+/// on the last Sunday of March to 01:00 UTC on the last Sunday of October.
+fn lisbon_offset(unix: i64) -> i64 {
+    let (year, _, _) = civil_from_days(unix.div_euclid(DAY));
+    let begins = last_sunday(year, 3) * DAY + 3600;
+    let ends = last_sunday(year, 10) * DAY + 3600;
+    if (begins..ends).contains(&unix) {
+        3600
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn parses_and_formats_past_midnight() {
+        let time = ServiceTime::parse("25:10:00").unwrap();
+        assert_eq!(time.hms(), (25, 10, 0));
+        assert_eq!(time.to_string(), "01:10");
+        assert_eq!(ServiceTime::parse("7:05:09").unwrap().to_string(), "07:05");
+        assert_eq!(ServiceTime::parse("08:60:00"), None);
+        assert_eq!(ServiceTime::parse("08:00"), None);
+        assert_eq!(ServiceTime::parse("1193046:28:16"), None);
+        assert_eq!(ServiceTime::parse("99999999999:00:00"), None);
+        assert!(ServiceTime::parse("1193046:28:15").is_some());
+
+        let date = ServiceDate::parse("20260105").unwrap();
+        assert_eq!(date.to_string(), "2026-01-05");
+        assert_eq!(ServiceDate::parse("20260229"), None);
+        assert_eq!(ServiceDate::of_unix(1_767_571_200), date);
+        assert_eq!(time.to_unix(date), 1_767_571_200 + DAY + 70 * 60);
+    }
+
+    #[test]
+    fn follows_lisbon_summer_time() {
+        let winter = ServiceDate::parse("20260328").unwrap();
+        let summer = ServiceDate::parse("20260330").unwrap();
+        let noon = ServiceTime::parse("12:00:00").unwrap();
+        assert_eq!(noon.to_unix(winter) % DAY, 12 * 3600);
+        assert_eq!(noon.to_unix(summer) % DAY, 11 * 3600);
+
+        // Clocks jump from 01:00 to 02:00 on 2026-03-29, so the service day
+        // starts at 23:00 UTC the evening before.
+        let change = ServiceDate::parse("20260329").unwrap();
+        assert_eq!(change.start_unix(), change.epoch_days() * DAY - 3600);
+
+        let (date, time) = ServiceTime::from_unix(noon.to_unix(summer));
+        assert_eq!((date, time), (summer, noon));
+        let (date, time) = ServiceTime::from_unix(summer.start_unix() - 1);
+        assert_eq!(
+            (date.to_string(), time.to_string()),
+            ("2026-03-29".into(), "23:59".into())
+        );
+    }
+}
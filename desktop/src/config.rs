@@ -1,8 +1,6 @@
-use crate::api_client;
-use carris_api::types::{CarrisAPI, Stop};
-use std::fs;
 use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::OnceLock;
 use xdg::BaseDirectories;
 
@@ -12,49 +10,19 @@ fn xdg_dirs() -> &'static BaseDirectories {
     XDG_DIRS.get_or_init(|| BaseDirectories::with_prefix("carris-ui"))
 }
 
-pub async fn ensure_stops_cached_with<A>(xdg: &BaseDirectories, api: &A) -> anyhow::Result<()>
-where
-    A: CarrisAPI,
-    A::Error: std::error::Error + Send + Sync + 'static,
-{
-    if xdg.find_cache_file("all-stops.json").is_some() {
-        log::info!("Found cached Stops");
-        return Ok(());
-    } else {
-        log::warn!("No stops cached");
-        let expected = xdg.get_cache_file("all-stops.json").expect("REASON");
-        log::warn!(
-            "Expecting cache at: {} and Exists? {}",
-            expected.display(),
-            expected.display()
-        );
-    }
-
-    log::info!("Check if stops file exists and if not download it");
-    let stops = api.get_all_stops().await?;
-    let json_stops = serde_json::to_vec_pretty(&stops)?;
-    let path = xdg.place_cache_file("all-stops.json")?;
-    fs::write(path, json_stops)?;
-
-    Ok(())
+/// Where the API client keeps its cached responses, the stop list among
+/// them.
+pub fn http_cache_dir() -> Option<PathBuf> {
+    xdg_dirs().get_cache_file("http")
 }
 
-pub async fn ensure_stops_cached_if_not_cached_download() -> anyhow::Result<()> {
-    ensure_stops_cached_with(xdg_dirs(), api_client()).await
-}
-
-pub async fn get_all_stops_cached() -> anyhow::Result<Vec<Stop>> {
-    ensure_stops_cached_if_not_cached_download().await?;
-
-    let path = xdg_dirs()
-        .find_cache_file("all-stops.json")
-        .ok_or_else(|| anyhow::anyhow!("cache file all_stops.json still missing after ensure"))?;
-
-    let bytes = fs::read(path)?;
-    log::info!("Try to serialize the stops into a list");
-    let stops: Vec<Stop> = serde_json::from_slice(&bytes)?;
-
-    Ok(stops)
+/// The GTFS feed scheduled departures are read from when offline,
+/// `CARRIS_GTFS_PATH` or `gtfs.zip` in the data directory.
+pub fn gtfs_feed_path() -> Option<PathBuf> {
+    match std::env::var_os("CARRIS_GTFS_PATH") {
+        Some(path) => Some(path.into()),
+        None => xdg_dirs().find_data_file("gtfs.zip"),
+    }
 }
 
 pub fn load_config_from_disk() {}
@@ -71,45 +39,31 @@ pub fn save_config() -> std::io::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use carris_api::types::Arrival;
-    use core::future::Future;
+    use crate::api_client;
+    use carris_api::types::CarrisAPI;
+    use carris_mock::MockServer;
     use tempfile::tempdir;
-    use xdg::BaseDirectories;
-
-    struct FakeApi {
-        stops: Vec<Stop>,
-        calls: std::sync::atomic::AtomicUsize,
-    }
-
-    impl CarrisAPI for FakeApi {
-        type Error = anyhow::Error;
 
-        fn new() -> Self {
-            Self {
-                stops: vec![],
-                calls: std::sync::atomic::AtomicUsize::new(0),
-            }
+    /// The app's client, retrying, caching and throttling, against the mock.
+    #[tokio::test]
+    async fn api_client_retries_and_caches_through_the_mock() {
+        let server = MockServer::start().await.unwrap();
+        let cache = tempdir().unwrap();
+        // Both are read once, when the client is first used, and no other
+        // test touches the environment.
+        unsafe {
+            std::env::set_var("CARRIS_API_BASE_URL", server.base_url());
+            std::env::set_var("XDG_CACHE_HOME", cache.path());
         }
 
-        fn new_with_base_url(_base_url: &str) -> Self {
-            Self::new()
-        }
-
-        fn arrivals_by_stop<'a>(
-            &'a self,
-            _stop: &'a str,
-        ) -> impl Future<Output = Result<Vec<Arrival>, Self::Error>> + 'a {
-            async move { Ok(vec![]) }
-        }
+        server.fail_next(1, 502);
+        let stops = api_client().get_all_stops().await.unwrap();
+        assert!(!stops.is_empty());
+        let again = api_client().get_all_stops().await.unwrap();
+        assert_eq!(stops, again);
 
-        fn get_all_stops<'a>(
-            &'a self,
-        ) -> impl Future<Output = Result<Vec<Stop>, Self::Error>> + 'a {
-            async move {
-                self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                Ok(self.stops.clone())
-            }
-        }
+        let statuses: Vec<u16> = server.requests().iter().map(|r| r.status).collect();
+        assert_eq!(statuses, [502, 200]);
+        assert!(cache.path().join("carris-ui/http/stops.json").exists());
     }
 }
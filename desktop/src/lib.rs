@@ -1,43 +1,206 @@
 mod config;
 
 use carris_api::api::CarrisClient;
-use carris_api::types::{Arrival, CarrisAPI, Stop};
-use slint::{Color, Image, ModelRc, SharedString, VecModel, Weak};
+use carris_api::cache::{CacheTtl, Cached, FsStore};
+use carris_api::gtfs::GtfsFeed;
+use carris_api::retry::{Retry, RetryPolicy, TokioTimer};
+use carris_api::search::StopSearch;
+use carris_api::throttle::{Throttled, TokenBucket};
+use carris_api::timetable::Timetable;
+use carris_api::types::{
+    Arrival, ArrivalStatus, CarrisAPI, Line, ServiceTime, Stop, alerts_for_stop,
+};
+use slint::{Color, Image, Model, ModelRc, SharedString, VecModel, Weak};
 use std::collections::HashMap;
 use std::path::Path;
-use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::{Mutex, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
 use tracing::Instrument;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 slint::include_modules!();
 
-pub fn api_client() -> &'static CarrisClient {
-    static API_CLIENT: OnceLock<CarrisClient> = OnceLock::new();
+/// Shared by every task, so the whole app stays within one rate limit.
+pub type ApiClient = Retry<Cached<Throttled<CarrisClient>, FsStore>, TokioTimer>;
 
-    API_CLIENT.get_or_init(|| CarrisClient::new())
+pub fn api_client() -> &'static ApiClient {
+    static API_CLIENT: OnceLock<ApiClient> = OnceLock::new();
+
+    API_CLIENT.get_or_init(|| {
+        // CARRIS_API_BASE_URL points the app at a local stand-in or caching proxy.
+        let client = match std::env::var("CARRIS_API_BASE_URL") {
+            Ok(base_url) => CarrisClient::new_with_base_url(&base_url),
+            Err(_) => CarrisClient::new(),
+        };
+        let store = config::http_cache_dir()
+            .map(FsStore::new)
+            .unwrap_or_default();
+        let throttled = Throttled::new(client, TokenBucket::default());
+        let cached = Cached::new(throttled, store, CacheTtl::default());
+        Retry::new(cached, RetryPolicy::default(), TokioTimer)
+    })
+}
+
+/// Scheduled departures from the downloaded GTFS feed, loaded on first use.
+async fn timetable() -> Option<&'static Timetable> {
+    static TIMETABLE: OnceCell<Option<Timetable>> = OnceCell::const_new();
+
+    TIMETABLE
+        .get_or_init(|| async {
+            let path = config::gtfs_feed_path()?;
+            let loaded =
+                tokio::task::spawn_blocking(move || GtfsFeed::open(path).map(|f| f.timetable()))
+                    .await;
+            match loaded {
+                Ok(Ok(timetable)) => Some(timetable),
+                Ok(Err(e)) => {
+                    log::warn!("Cannot load the GTFS feed: {e}");
+                    None
+                }
+                Err(e) => {
+                    log::error!("Loading the GTFS feed panicked: {e}");
+                    None
+                }
+            }
+        })
+        .await
+        .as_ref()
+}
+
+fn lines() -> &'static Mutex<HashMap<String, Line>> {
+    static LINES: OnceLock<Mutex<HashMap<String, Line>>> = OnceLock::new();
+
+    LINES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn stops() -> &'static Mutex<HashMap<String, Stop>> {
+    static STOPS: OnceLock<Mutex<HashMap<String, Stop>>> = OnceLock::new();
+
+    STOPS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn stop_search() -> &'static Mutex<StopSearch> {
+    static STOP_SEARCH: OnceLock<Mutex<StopSearch>> = OnceLock::new();
+
+    STOP_SEARCH.get_or_init(|| Mutex::new(StopSearch::default()))
+}
+
+/// Municipality names keyed by municipality id.
+fn municipalities() -> &'static Mutex<HashMap<String, String>> {
+    static MUNICIPALITIES: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+    MUNICIPALITIES.get_or_init(|| Mutex::new(HashMap::new()))
 }
 
 fn ui() -> MainWindow {
     MainWindow::new().unwrap()
 }
 
+fn color_from_hex(hex: &str) -> Option<Color> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color::from_rgb_u8(
+        (rgb >> 16) as u8,
+        (rgb >> 8) as u8,
+        rgb as u8,
+    ))
+}
+
 impl From<Arrival> for BusArrival {
     fn from(arrival: Arrival) -> Self {
+        let now = now_unix_secs();
+        let line_id = arrival.line_id.to_string();
+        let line = lines().lock().unwrap().get(&line_id).cloned();
+
+        let (line_color, line_text_color) = line
+            .as_ref()
+            .and_then(|l| Some((color_from_hex(&l.color)?, color_from_hex(&l.text_color)?)))
+            .unwrap_or((
+                Color::from_rgb_u8(0x3d, 0x3d, 0x3d),
+                Color::from_rgb_u8(0xff, 0xff, 0xff),
+            ));
+
         BusArrival {
-            number: arrival.line_id as i32,
+            line: line.map(|l| l.short_name).unwrap_or(line_id).into(),
+            line_color,
+            line_text_color,
 
             arrival_time: arrival
-                .scheduled_arrival
-                .unwrap_or_else(|| "--:--".into())
+                .scheduled_arrival_unix
+                .map(|unix| ServiceTime::from_unix(unix).1)
+                .or_else(|| arrival.scheduled_time())
+                .map_or_else(|| "--:--".into(), |time| time.to_string())
                 .into(),
 
+            status: describe_status(arrival.status(now)).into(),
+            countdown: match arrival.minutes_until(now) {
+                Some(minutes) if minutes <= 0 => "now".into(),
+                Some(minutes) => format!("{minutes} min").into(),
+                None => SharedString::new(),
+            },
+
             direction: arrival.headsign.into(),
         }
     }
 }
 
+fn describe_status(status: ArrivalStatus) -> String {
+    match status {
+        ArrivalStatus::Scheduled => "Scheduled".into(),
+        ArrivalStatus::Realtime { delay_secs } => match delay_secs / 60 {
+            0 => "On time".into(),
+            late if late > 0 => format!("{late} min late"),
+            early => format!("{} min early", -early),
+        },
+        ArrivalStatus::Arrived => "At the stop".into(),
+        ArrivalStatus::Departed => "Departed".into(),
+        ArrivalStatus::Cancelled => "Cancelled".into(),
+    }
+}
+
+/// How many lines to list under a stop name.
+const SUPPORTING_LINES: usize = 4;
+
+/// Tells apart stops sharing a name, such as the two sides of a road:
+/// "Almada · 3001, 3002 · 140012", leaving out what is unknown.
+fn stop_supporting_text(stop: &Stop) -> String {
+    let mut parts = Vec::with_capacity(3);
+
+    if let Some(name) = stop
+        .municipality_id
+        .as_ref()
+        .and_then(|id| municipalities().lock().unwrap().get(id).cloned())
+    {
+        parts.push(name);
+    }
+
+    if !stop.line_ids.is_empty() {
+        let lines = lines().lock().unwrap();
+        let mut names: Vec<String> = stop
+            .line_ids
+            .iter()
+            .take(SUPPORTING_LINES)
+            .map(|id| {
+                lines
+                    .get(id)
+                    .map_or_else(|| id.clone(), |l| l.short_name.clone())
+            })
+            .collect();
+        if stop.line_ids.len() > SUPPORTING_LINES {
+            names.push("…".into());
+        }
+        parts.push(names.join(", "));
+    }
+
+    parts.push(stop.id.clone());
+    parts.join(" · ")
+}
+
 fn stops_to_models(stops: Vec<Stop>) -> (ModelRc<ListItem>, ModelRc<SharedString>) {
     let mut items = Vec::with_capacity(stops.len());
     let mut ids = Vec::with_capacity(stops.len());
@@ -46,8 +209,8 @@ fn stops_to_models(stops: Vec<Stop>) -> (ModelRc<ListItem>, ModelRc<SharedString
         ids.push(SharedString::from(s.id.clone()));
 
         items.push(ListItem {
-            text: s.long_name.into(),     // what user sees
-            supporting_text: s.id.into(), // optional (e.g. municipality)
+            supporting_text: stop_supporting_text(&s).into(),
+            text: s.long_name.into(), // what user sees
             avatar_icon: Image::default(),
             avatar_text: SharedString::new(),
             avatar_background: Color::from_argb_u8(0, 0, 0, 0),
@@ -68,53 +231,41 @@ pub fn main() {
     env_logger::init();
     let ui = ui();
 
-    let lookup: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
-    let lookup_for_task = lookup.clone();
-    let lookup_for_stop = lookup.clone();
     let ui_handle_stops = ui.clone_strong();
 
-    fill_searchbar_with_options(&ui, lookup_for_task);
+    fill_searchbar_with_options(&ui);
 
-    update_selected_bus_stop(&ui, Arc::clone(&lookup_for_stop));
+    update_selected_bus_stop(&ui);
 
     let ui_searchbar_action_button_weak = ui.as_weak();
-    let lookup_searchbar_action_button_cb = lookup.clone();
     ui.on_searchbar_bus_station_clicked(move |index| {
-        spawn_action_button_to_element(
-            ui_searchbar_action_button_weak.clone(),
-            lookup_searchbar_action_button_cb.clone(),
-            index,
-        );
+        spawn_action_button_to_element(ui_searchbar_action_button_weak.clone(), index);
     });
 
     let ui_searchbar_weak = ui.as_weak();
-    let lookup_searchbar_cb = lookup.clone();
 
     ui.on_filter_searchbar_options(move |text: SharedString| {
-        spawn_filter_search(ui_searchbar_weak.clone(), lookup_searchbar_cb.clone(), text);
+        spawn_filter_search(ui_searchbar_weak.clone(), text);
     });
 
+    load_lines();
+
     set_bus_arrivals_for_station(&ui);
 
     ui.run().unwrap();
 }
 
-fn spawn_action_button_to_element(
-    ui_weak: Weak<MainWindow>,
-    lookup: Arc<Mutex<HashMap<String, String>>>,
-    index: i32,
-) {
+fn spawn_action_button_to_element(ui_weak: Weak<MainWindow>, index: i32) {
     slint::spawn_local(async_compat::Compat::new(async move {
-        log::info!("Search for index {}", index);
+        let stop_id = ui_weak
+            .upgrade()
+            .and_then(|ui| ui.get_bus_stations_ids().row_data(index as usize));
+        log::info!("Search for index {index}, stop {stop_id:?}");
     }))
     .expect("Cannot connect action button and event");
 }
 
-fn spawn_filter_search(
-    ui_weak: Weak<MainWindow>,
-    lookup: Arc<Mutex<HashMap<String, String>>>,
-    text: SharedString,
-) {
+fn spawn_filter_search(ui_weak: Weak<MainWindow>, text: SharedString) {
     slint::spawn_local(async_compat::Compat::new(
         {
             let text = text.clone();
@@ -126,32 +277,22 @@ fn spawn_filter_search(
 
                 let query = text.to_string();
 
-                let map = lookup.lock().unwrap();
-
-                let results = filter_search_string(&query, &map, 25);
+                let results: Vec<Stop> = {
+                    let stops = stops().lock().unwrap();
+                    stop_search()
+                        .lock()
+                        .unwrap()
+                        .search(&query, 25)
+                        .into_iter()
+                        .filter_map(|id| stops.get(id).cloned())
+                        .collect()
+                };
                 log::info!("Got a total of {} results", results.len());
 
                 if let Some(ui) = ui_weak.upgrade() {
-                    let items = results.iter().map(|result| {
-                        let key = result.clone();
-
-                        let supporting = map.get(key.as_str()).cloned().unwrap_or_default();
-
-                        ListItem {
-                            text: key.into(),
-                            supporting_text: supporting.into(),
-                            avatar_icon: Image::default(),
-                            avatar_text: SharedString::new(),
-                            avatar_background: Color::from_argb_u8(0, 0, 0, 0),
-                            avatar_foreground: Color::from_argb_u8(0, 0, 0, 0),
-                            action_button_icon: Image::load_from_path(Path::new(
-                                "desktop/ui/slint-logo.svg",
-                            ))
-                            .unwrap(), //Image::default(),
-                        }
-                    });
-
-                    ui.set_bus_stations(ModelRc::new(VecModel::from_iter(items)));
+                    let (items, ids) = stops_to_models(results);
+                    ui.set_bus_stations(items);
+                    ui.set_bus_stations_ids(ids);
                 } else {
                     log::error!("Failed to upgrade UI weak reference");
                 }
@@ -162,62 +303,75 @@ fn spawn_filter_search(
     .expect("Cannot filter search options in searchbar");
 }
 
-fn filter_search_string(
-    query: &str,
-    lookup: &HashMap<String, String>,
-    limit: usize,
-) -> Vec<SharedString> {
-    let lquery = query.trim().to_lowercase();
-    if lquery.is_empty() {
-        return vec![];
-    }
-
-    let keys: Vec<_> = lookup.keys().cloned().collect();
-
-    let results = keys
-        .into_iter()
-        .filter(|name| name.to_lowercase().contains(&lquery))
-        //.take(limit)
-        .map(|name| SharedString::from(name))
-        .collect();
-
-    return results;
-}
-
-fn update_selected_bus_stop(ui: &MainWindow, lookup: Arc<Mutex<HashMap<String, String>>>) {
+fn update_selected_bus_stop(ui: &MainWindow) {
     let ui_for_cb = ui.clone_strong();
-    ui.on_bus_station_selected(move |search_text: SharedString| {
-        let name = search_text.to_string();
-
-        let stop_id = {
-            let map = lookup.lock().unwrap();
-            map.get(&name).cloned()
-        };
-
-        let Some(stop_id) = stop_id else {
-            log::error!("No stop id found for selection: {name}");
+    ui.on_bus_station_selected(move |stop_id: SharedString| {
+        let stop_id = stop_id.to_string();
+
+        let Some(name) = stops()
+            .lock()
+            .unwrap()
+            .get(&stop_id)
+            .map(|s| s.long_name.clone())
+        else {
+            log::error!("Selected stop {stop_id} is not known");
             return;
         };
 
         log::info!("Selected stop: {stop_id} with long name {name}");
         let ui_for_task = ui_for_cb.clone_strong();
         slint::spawn_local(async_compat::Compat::new(async move {
-            match api_client().arrivals_by_stop(&stop_id).await {
-                Ok(arrivals) => {
-                    let future_arrivals = only_future_arrivals(arrivals);
-
+            match future_arrivals(&stop_id).await {
+                Ok(future_arrivals) => {
                     let bus_arrivals: Vec<BusArrival> =
                         future_arrivals.into_iter().map(BusArrival::from).collect();
 
                     ui_for_task.set_next_busses(ModelRc::new(VecModel::from(bus_arrivals)));
                 }
-                Err(e) => log::error!("Failed to load arrivals for {stop_id}: {e}"),
+                Err(e) => {
+                    log::error!("Failed to load arrivals for {stop_id}: {e}");
+                    ui_for_task.set_busstation_label(describe_error(&e).into());
+                }
             }
+
+            show_alerts_for_stop(&ui_for_task, &stop_id).await;
         }))
         .unwrap();
     });
 }
 
+async fn show_alerts_for_stop(ui: &MainWindow, stop_id: &str) {
+    let Some(stop) = stops().lock().unwrap().get(stop_id).cloned() else {
+        ui.set_alert_banner(SharedString::new());
+        return;
+    };
+
+    match api_client().get_alerts().await {
+        Ok(alerts) => {
+            let now = now_unix_secs();
+            let banner = alerts_for_stop(&alerts, &stop)
+                .into_iter()
+                .filter(|a| a.is_active(now))
+                .filter_map(|a| a.header_text.get("pt"))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            ui.set_alert_banner(banner.into());
+        }
+        Err(e) => log::error!("Failed to load alerts for {stop_id}: {e}"),
+    }
+}
+
+/// What to tell the user when a request for a stop failed.
+fn describe_error(e: &carris_api::Error) -> &'static str {
+    match e {
+        carris_api::Error::StopNotFound(_) => "This stop does not exist",
+        carris_api::Error::RateLimited { .. } => "Too many requests, try again shortly",
+        e if e.is_offline() => "You are offline",
+        _ => "Cannot load arrivals",
+    }
+}
+
 fn now_unix_secs() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -227,7 +381,46 @@ fn now_unix_secs() -> i64 {
 
 fn only_future_arrivals(arrivals: impl IntoIterator<Item = Arrival>) -> Vec<Arrival> {
     let now = now_unix_secs();
-    arrivals.into_iter().filter(|a| a.is_future(now)).collect()
+    arrivals
+        .into_iter()
+        .filter(|a| a.status(now) != ArrivalStatus::Departed)
+        .collect()
+}
+
+/// How many scheduled departures to show when offline.
+const SCHEDULED_DEPARTURES: usize = 10;
+
+/// Upcoming arrivals at `stop_id`, from the timetable when the API cannot
+/// be reached.
+async fn future_arrivals(stop_id: &str) -> Result<Vec<Arrival>, carris_api::Error> {
+    match api_client().arrivals_by_stop(stop_id).await {
+        Ok(arrivals) => Ok(only_future_arrivals(arrivals)),
+        Err(e) if e.is_offline() => match timetable().await {
+            Some(timetable) => {
+                log::info!("Offline, showing scheduled departures for {stop_id}");
+                Ok(timetable.next_departures(stop_id, now_unix_secs(), SCHEDULED_DEPARTURES))
+            }
+            None => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+fn load_lines() {
+    slint::spawn_local(async_compat::Compat::new(async move {
+        match api_client().get_all_lines().await {
+            Ok(all_lines) => {
+                log::info!("Lines: {:?}", all_lines.len());
+                let map = all_lines
+                    .into_iter()
+                    .map(|line| (line.id.clone(), line))
+                    .collect();
+                *lines().lock().unwrap() = map;
+            }
+            Err(e) => log::error!("Failed to load lines: {e}"),
+        }
+    }))
+    .expect("Cannot load lines");
 }
 
 fn set_bus_arrivals_for_station(ui: &MainWindow) {
@@ -237,38 +430,54 @@ fn set_bus_arrivals_for_station(ui: &MainWindow) {
         // TODO don't hard code this
         let bus_stop_id = "020387";
         log::info!("Getting bus data for {} id", bus_stop_id);
-        let result = api_client().arrivals_by_stop(bus_stop_id).await.unwrap();
-
-        let future_arrivals = only_future_arrivals(result);
-
-        let bus_arrivals: Vec<BusArrival> =
-            future_arrivals.into_iter().map(BusArrival::from).collect();
-        log::info!("Length of the content is: {}", bus_arrivals.len());
-        let model = ModelRc::new(VecModel::from(bus_arrivals));
-        ui_handle_busses.set_next_busses(model);
+        match future_arrivals(bus_stop_id).await {
+            Ok(future_arrivals) => {
+                let bus_arrivals: Vec<BusArrival> =
+                    future_arrivals.into_iter().map(BusArrival::from).collect();
+                log::info!("Length of the content is: {}", bus_arrivals.len());
+                let model = ModelRc::new(VecModel::from(bus_arrivals));
+                ui_handle_busses.set_next_busses(model);
+            }
+            // Keep whatever arrivals are already shown instead of blanking
+            // the display.
+            Err(e) => {
+                log::error!("Failed to load arrivals for {bus_stop_id}: {e}");
+                ui_handle_busses.set_busstation_label(describe_error(&e).into());
+            }
+        }
     }))
     .expect("Cannot get Bus Data");
 }
 
-fn fill_searchbar_with_options(
-    ui: &MainWindow,
-    lookup_for_stop: Arc<Mutex<HashMap<String, String>>>,
-) {
+fn fill_searchbar_with_options(ui: &MainWindow) {
     let ui_handle_stops = ui.clone_strong();
 
     slint::spawn_local(async_compat::Compat::new(async move {
-        match config::get_all_stops_cached().await {
-            Ok(stops) => {
-                log::info!("Stops: {:?}", stops.len());
-                let lookup_stops = stops.clone();
-                let (bus_stations, bus_station_ids) = stops_to_models(stops);
-                ui_handle_stops.set_bus_stations(bus_stations);
+        let all_municipalities = match api_client().get_municipalities().await {
+            Ok(all) => {
+                *municipalities().lock().unwrap() =
+                    all.iter().map(|m| (m.id.clone(), m.name.clone())).collect();
+                all
+            }
+            Err(e) => {
+                log::error!("Failed to load municipalities: {e}");
+                Vec::new()
+            }
+        };
 
-                let mut map = HashMap::with_capacity(lookup_stops.len());
-                for s in lookup_stops {
-                    map.insert(s.long_name, s.id);
-                }
-                *lookup_for_stop.lock().unwrap() = map;
+        // Cached for a day, and served from the cache while offline.
+        match api_client().get_all_stops().await {
+            Ok(all_stops) => {
+                log::info!("Stops: {:?}", all_stops.len());
+                *stop_search().lock().unwrap() = StopSearch::new(&all_stops, &all_municipalities);
+                let by_id = all_stops
+                    .iter()
+                    .map(|s| (s.id.clone(), s.clone()))
+                    .collect();
+                let (bus_stations, bus_station_ids) = stops_to_models(all_stops);
+                ui_handle_stops.set_bus_stations(bus_stations);
+                ui_handle_stops.set_bus_stations_ids(bus_station_ids);
+                *stops().lock().unwrap() = by_id;
             }
             Err(e) => {
                 log::error!("Failed to load stops: {e}");
@@ -290,3 +499,19 @@ fn android_main(android_app: slint::android::AndroidApp) {
     MaterialWindowAdapter::get(&ui).set_disable_hover(true);
     ui.run().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_line_colours() {
+        assert_eq!(
+            color_from_hex("#C61D23"),
+            Some(Color::from_rgb_u8(0xc6, 0x1d, 0x23))
+        );
+        assert_eq!(color_from_hex("C61D23"), None);
+        assert_eq!(color_from_hex("#FFF"), None);
+        assert_eq!(color_from_hex("#GGGGGG"), None);
+    }
+}
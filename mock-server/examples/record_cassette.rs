@@ -0,0 +1,40 @@
+//! Records `api-client/fixtures/cassette` from the mock, the cassette the
+//! `cassette` tests replay.
+//!
+//! ```sh
+//! cargo run -p carris-mock --example record_cassette
+//! ```
+
+use carris_api::api::CarrisClient;
+use carris_api::cassette::Cassette;
+use carris_api::types::CarrisAPI;
+use carris_mock::MockServer;
+use std::path::Path;
+
+/// 2026-01-05 08:00 in Lisbon.
+const NOW: i64 = 1_767_600_000;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mock = MockServer::start().await.expect("cannot start the mock");
+    mock.set_now(NOW);
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../api-client/fixtures/cassette");
+    let _ = std::fs::remove_dir_all(&dir);
+    let cassette = Cassette::record(CarrisClient::new_with_base_url(mock.base_url()), &dir);
+
+    let stops = cassette
+        .get_all_stops()
+        .await
+        .expect("cannot record /stops");
+    let arrivals = cassette
+        .arrivals_by_stop("020387")
+        .await
+        .expect("cannot record the arrivals");
+    println!(
+        "recorded {} stops and {} arrivals into {}",
+        stops.len(),
+        arrivals.len(),
+        dir.display()
+    );
+}
@@ -0,0 +1,529 @@
+//! In-process stand-in for the Carris Metropolitana v2 API.
+//!
+//! [`MockServer`] serves JSON fixtures over real HTTP on a local port, so
+//! clients are tested through the same request, status and decoding path
+//! they use against the live API. A fixture file `arrivals/by_stop/020387.json`
+//! answers `GET /v2/arrivals/by_stop/020387`. Request paths and fixture
+//! names are both percent-decoded, so `lines/41%7C1234.json`, spelled so
+//! that every filesystem accepts it, answers `/v2/lines/41|1234` as well as
+//! `/v2/lines/41%7C1234`.
+//!
+//! The `*_arrival_unix` times of arrival fixtures are seconds relative to
+//! the mock clock, which follows the system clock until [`MockServer::set_now`]
+//! pins it.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Largest request head the mock accepts.
+const MAX_HEAD: usize = 16 * 1024;
+
+pub struct MockServer {
+    base_url: String,
+    state: Arc<State>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    fixtures: Mutex<HashMap<String, Vec<u8>>>,
+    knobs: Mutex<Knobs>,
+    requests: Mutex<Vec<Served>>,
+    errors: Mutex<Vec<io::Error>>,
+}
+
+/// A request the mock answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Served {
+    /// Path without the `/v2` prefix.
+    pub path: String,
+    pub status: u16,
+}
+
+#[derive(Default)]
+struct Knobs {
+    latency: Duration,
+    fail_next: VecDeque<u16>,
+    fail_paths: HashMap<String, u16>,
+    now_unix: Option<i64>,
+}
+
+impl MockServer {
+    /// Serves the fixtures bundled with this crate.
+    pub async fn start() -> io::Result<Self> {
+        Self::with_fixtures(fixtures_dir()).await
+    }
+
+    /// Serves every `.json` file below `dir`.
+    pub async fn with_fixtures(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut fixtures = HashMap::new();
+        load_fixtures(dir.as_ref(), dir.as_ref(), &mut fixtures)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}/v2", listener.local_addr()?);
+        let state = Arc::new(State {
+            fixtures: Mutex::new(fixtures),
+            ..Default::default()
+        });
+
+        let accepting = state.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = accepting.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(&state, stream).await {
+                        state.errors.lock().unwrap().push(e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            base_url,
+            state,
+            task,
+        })
+    }
+
+    /// Pass this to `CarrisClient::new_with_base_url`.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Adds or replaces the response for `path`, e.g. `/stops`.
+    pub fn set_fixture(&self, path: &str, body: impl Into<Vec<u8>>) {
+        self.state
+            .fixtures
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), body.into());
+    }
+
+    /// Delays every response by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.knobs.lock().unwrap().latency = latency;
+    }
+
+    /// Answers the next `count` requests with `status`. A 429 comes with
+    /// `Retry-After: 1`.
+    pub fn fail_next(&self, count: usize, status: u16) {
+        let mut knobs = self.state.knobs.lock().unwrap();
+        knobs.fail_next.extend(std::iter::repeat_n(status, count));
+    }
+
+    /// Answers every request for `path` with `status`, or serves it normally
+    /// again for `None`.
+    pub fn fail_path(&self, path: &str, status: Option<u16>) {
+        let mut knobs = self.state.knobs.lock().unwrap();
+        match status {
+            Some(status) => knobs.fail_paths.insert(path.to_owned(), status),
+            None => knobs.fail_paths.remove(path),
+        };
+    }
+
+    /// Pins the mock clock arrival times are relative to.
+    pub fn set_now(&self, now_unix: i64) {
+        self.state.knobs.lock().unwrap().now_unix = Some(now_unix);
+    }
+
+    /// Moves the mock clock forward, pinning it if it was not yet.
+    pub fn advance(&self, by: Duration) {
+        let mut knobs = self.state.knobs.lock().unwrap();
+        let now = knobs.now_unix.unwrap_or_else(system_now_unix);
+        knobs.now_unix = Some(now + by.as_secs() as i64);
+    }
+
+    /// All requests answered so far.
+    pub fn requests(&self) -> Vec<Served> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Takes the errors of connections that could not be served, such as
+    /// malformed requests or clients hanging up early.
+    pub fn take_errors(&self) -> Vec<io::Error> {
+        std::mem::take(&mut *self.state.errors.lock().unwrap())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The fixtures shipped with this crate.
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+fn load_fixtures(root: &Path, dir: &Path, out: &mut HashMap<String, Vec<u8>>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            load_fixtures(root, &path, out)?;
+        } else if path.extension().is_some_and(|ext| ext == "json") {
+            let relative = path.strip_prefix(root).unwrap().with_extension("");
+            let key = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .fold(String::new(), |key, part| key + "/" + &part);
+            out.insert(percent_decode(&key), std::fs::read(&path)?);
+        }
+    }
+    Ok(())
+}
+
+fn system_now_unix() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_secs() as i64
+}
+
+struct Request {
+    method: String,
+    path: String,
+    if_none_match: Option<String>,
+}
+
+async fn read_request(stream: &mut TcpStream) -> io::Result<Request> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 || head.len() + n > MAX_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incomplete request head",
+            ));
+        }
+        head.extend_from_slice(&chunk[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let target = request_line.next().unwrap_or_default();
+    let target = target.split('?').next().unwrap_or_default();
+    let path = percent_decode(target.strip_prefix("/v2").unwrap_or(target));
+
+    let if_none_match = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("if-none-match"))
+        .map(|(_, value)| value.trim().to_owned());
+
+    Ok(Request {
+        method,
+        path,
+        if_none_match,
+    })
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+async fn serve(state: &State, mut stream: TcpStream) -> io::Result<()> {
+    let request = read_request(&mut stream).await?;
+
+    let (latency, failure, now_unix) = {
+        let mut knobs = state.knobs.lock().unwrap();
+        let failure = knobs
+            .fail_next
+            .pop_front()
+            .or_else(|| knobs.fail_paths.get(&request.path).copied());
+        (knobs.latency, failure, knobs.now_unix)
+    };
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+
+    let fixture = state.fixtures.lock().unwrap().get(&request.path).cloned();
+    let response = match (failure, fixture) {
+        _ if request.method != "GET" => Response::error(405),
+        (Some(status), _) => Response::error(status),
+        (None, None) => Response::error(404),
+        (None, Some(body)) => {
+            let body = if request.path.starts_with("/arrivals/") {
+                shift_arrivals(&body, now_unix.unwrap_or_else(system_now_unix))
+            } else {
+                body
+            };
+            let etag = format!("\"{:016x}\"", fnv1a(&body));
+            if request.if_none_match.as_deref() == Some(etag.as_str()) {
+                Response::new(304, Vec::new()).header("ETag", etag)
+            } else {
+                Response::new(200, body).header("ETag", etag)
+            }
+        }
+    };
+
+    state.requests.lock().unwrap().push(Served {
+        path: request.path,
+        status: response.status,
+    });
+    stream.write_all(&response.into_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Turns the relative arrival times of a fixture into unix times.
+fn shift_arrivals(body: &[u8], now_unix: i64) -> Vec<u8> {
+    let Ok(mut arrivals) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return body.to_vec();
+    };
+    for arrival in arrivals.as_array_mut().into_iter().flatten() {
+        for (key, value) in arrival.as_object_mut().into_iter().flatten() {
+            if key.ends_with("_arrival_unix")
+                && let Some(offset) = value.as_i64()
+            {
+                *value = (now_unix + offset).into();
+            }
+        }
+    }
+    serde_json::to_vec(&arrivals).expect("a parsed value serializes")
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    fn error(status: u16) -> Self {
+        let body = format!(r#"{{"error":"{}"}}"#, reason(status)).into_bytes();
+        let response = Self::new(status, body);
+        if status == 429 {
+            response.header("Retry-After", "1".into())
+        } else {
+            response
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        out += "Content-Type: application/json\r\n";
+        out += &format!("Content-Length: {}\r\n", self.body.len());
+        out += "Connection: close\r\n";
+        for (name, value) in &self.headers {
+            out += &format!("{name}: {value}\r\n");
+        }
+        out += "\r\n";
+
+        let mut bytes = out.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        304 => "Not Modified",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use carris_api::Error;
+    use carris_api::api::CarrisClient;
+    use carris_api::cache::{CacheTtl, Cached, MemoryStore};
+    use carris_api::types::CarrisAPI;
+
+    #[tokio::test]
+    async fn serves_fixtures_relative_to_the_mock_clock() {
+        let server = MockServer::start().await.unwrap();
+        let client = CarrisClient::new_with_base_url(server.base_url());
+        server.set_now(1_700_000_000);
+
+        let stops = client.get_all_stops().await.unwrap();
+        assert_eq!(stops.len(), 2);
+
+        let arrivals = client.arrivals_by_stop("020387").await.unwrap();
+        assert_eq!(arrivals[0].scheduled_arrival_unix, Some(1_700_000_180));
+        assert_eq!(arrivals[0].estimated_arrival_unix, Some(1_700_000_240));
+
+        server.advance(Duration::from_secs(60));
+        let arrivals = client.arrivals_by_stop("020387").await.unwrap();
+        assert_eq!(arrivals[0].scheduled_arrival_unix, Some(1_700_000_240));
+    }
+
+    #[tokio::test]
+    async fn serves_every_endpoint() {
+        let server = MockServer::start().await.unwrap();
+        let client = CarrisClient::new_with_base_url(server.base_url());
+        server.set_now(1_767_600_000);
+
+        let pattern = client.get_pattern("1001_0_1").await.unwrap();
+        assert_eq!(pattern.path.len(), 2);
+        let shape = client.get_shape(&pattern.shape_id).await.unwrap();
+        assert_eq!(shape.points.len(), 5);
+
+        let trip_id = &pattern.trips[1].trip_ids[0];
+        let by_trip = client.arrivals_by_trip(trip_id).await.unwrap();
+        assert_eq!(by_trip.len(), pattern.path.len());
+        assert_eq!(by_trip[0].trip_id.as_ref(), Some(trip_id));
+        let by_pattern = client.arrivals_by_pattern(&pattern.id).await.unwrap();
+        assert!(
+            by_pattern
+                .iter()
+                .all(|a| a.pattern_id.as_ref() == Some(&pattern.id))
+        );
+
+        let vehicles = client.vehicles_by_line("1001").await.unwrap();
+        assert_eq!(vehicles.len(), 2);
+        let alerts = client.get_alerts().await.unwrap();
+        assert!(alerts[0].affects_line("1001") && alerts[0].is_active(1_767_600_000));
+
+        assert_eq!(client.get_districts().await.unwrap().len(), 2);
+        assert_eq!(client.get_regions().await.unwrap()[0].id, "PT170");
+        assert!(client.get_line("1001").await.is_ok());
+        assert!(client.get_municipalities().await.is_ok());
+        assert!(server.requests().iter().all(|r| r.status == 200));
+    }
+
+    #[tokio::test]
+    async fn injected_errors_reach_the_client() {
+        let server = MockServer::start().await.unwrap();
+        let client = CarrisClient::new_with_base_url(server.base_url());
+
+        server.fail_next(1, 502);
+        assert_eq!(
+            client.get_all_lines().await,
+            Err(Error::Status {
+                status: 502,
+                retry_after_secs: None
+            })
+        );
+        assert!(client.get_all_lines().await.is_ok());
+
+        server.fail_next(1, 429);
+        assert_eq!(
+            client.get_all_lines().await,
+            Err(Error::RateLimited {
+                retry_after_secs: Some(1)
+            })
+        );
+
+        assert_eq!(
+            client.arrivals_by_stop("999999").await,
+            Err(Error::StopNotFound("999999".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn matches_fixtures_by_decoded_path() {
+        let server = MockServer::start().await.unwrap();
+        let client = CarrisClient::new_with_base_url(server.base_url());
+        let line = std::fs::read(fixtures_dir().join("lines/1001.json")).unwrap();
+        server.set_fixture("/lines/41|1234 a", line);
+
+        assert!(client.get_line("41|1234 a").await.is_ok());
+        assert_eq!(server.requests()[0].path, "/lines/41|1234 a");
+        assert_eq!(percent_decode("/a%2Fb%zz%4"), "/a/b%zz%4");
+    }
+
+    #[tokio::test]
+    async fn reports_connection_errors() {
+        let server = MockServer::start().await.unwrap();
+        let addr = server
+            .base_url()
+            .trim_start_matches("http://")
+            .trim_end_matches("/v2");
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        stream.shutdown().await.unwrap();
+        let _ = stream.read(&mut [0; 1]).await;
+
+        // The error is recorded after the connection closes.
+        let errors = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let errors = server.take_errors();
+                if !errors.is_empty() {
+                    return errors;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("no connection error reported");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), io::ErrorKind::InvalidData);
+        assert!(server.take_errors().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stale_cache_entries_are_revalidated_with_a_304() {
+        let server = MockServer::start().await.unwrap();
+        let ttl = CacheTtl {
+            network: Duration::ZERO,
+            ..Default::default()
+        };
+        let client = Cached::new(
+            CarrisClient::new_with_base_url(server.base_url()),
+            MemoryStore::new(),
+            ttl,
+        );
+
+        let first = client.get_municipalities().await.unwrap();
+        let second = client.get_municipalities().await.unwrap();
+        assert_eq!(first, second);
+
+        let statuses: Vec<_> = server.requests().iter().map(|r| r.status).collect();
+        assert_eq!(statuses, [200, 304]);
+    }
+}